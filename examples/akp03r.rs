@@ -1,8 +1,9 @@
 use image::open;
 use mirajazz::{
-    device::{list_devices, Device, DeviceQuery},
+    device::{Device, DeviceQuery},
     error::MirajazzError,
-    types::{DeviceInput, ImageFormat, ImageMirroring, ImageMode, ImageRotation},
+    state::DeviceStateUpdate,
+    types::{DeviceInput, EncodingOptions, ImageFormat, ImageMode, ImageTransform},
 };
 
 const QUERY: DeviceQuery = DeviceQuery::new(65440, 1, 0x0300, 0x1003);
@@ -10,60 +11,52 @@ const QUERY: DeviceQuery = DeviceQuery::new(65440, 1, 0x0300, 0x1003);
 const IMAGE_FORMAT: ImageFormat = ImageFormat {
     mode: ImageMode::JPEG,
     size: (60, 60),
-    rotation: ImageRotation::Rot0,
-    mirror: ImageMirroring::None,
+    transform: ImageTransform::NONE,
+    encoding: EncodingOptions::Default,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), MirajazzError> {
     println!("Mirajazz example for Ajazz AKP03R");
 
-    for dev in list_devices(&[QUERY]).await? {
-        println!(
-            "Connecting to {:04X}:{:04X}, {}",
-            dev.vendor_id,
-            dev.product_id,
-            dev.serial_number.clone().unwrap()
-        );
-
-        // Connect to the device
-        let device = Device::connect(&dev, 2, 9, 3).await?;
-
+    // Connect to every matching device, key and encoder counts come from the known model
+    for device in Device::open_all(QUERY).await? {
         // Print out some info from the device
         println!(
             "Connected to '{}', fw: {:?}",
-            device.serial_number(),
+            device.id(),
             device.firmware_version
         );
 
-        device.set_brightness(50).await?;
-        device.clear_all_button_images().await?;
-
         // Use image-rs to load an image
         let image = open("examples/test.jpg").unwrap();
 
         println!("Key count: {}", device.key_count());
-        // Write it to the device
-        for i in 0..device.key_count() as u8 {
-            device
-                .set_button_image(i, IMAGE_FORMAT, image.clone())
-                .await?;
-        }
+        // Write it to every key
+        let images: Vec<_> = (0..device.key_count() as u8)
+            .map(|i| (i, image.clone()))
+            .collect();
 
-        // Flush
-        device.flush().await?;
+        device.show_splash(&images, IMAGE_FORMAT, 50).await?;
 
         let reader = device.get_reader(|key, state| {
             println!("Key {}, state {}", key, state);
 
             Ok(DeviceInput::NoData)
-        });
-
-        loop {
-            match reader.read(None).await {
-                Ok(updates) => updates,
-                Err(_) => break,
-            };
+        })?;
+
+        while let Ok(updates) = reader.read(None).await {
+            for update in updates {
+                match update {
+                    DeviceStateUpdate::ButtonDown(key) => println!("Key {} down", key),
+                    DeviceStateUpdate::ButtonUp(key) => println!("Key {} up", key),
+                    DeviceStateUpdate::EncoderTwist(encoder, value) => {
+                        println!("Encoder {} twisted by {}", encoder, value)
+                    }
+                    // Updates are non-exhaustive, new kinds are added over time
+                    _ => {}
+                }
+            }
         }
 
         drop(reader);
@@ -1,10 +1,13 @@
 use image::open;
 use mirajazz::{
-    device::{list_devices, Device, DeviceQuery},
+    device::{list_devices, ConnectOptions, Device, DeviceQuery},
     error::MirajazzError,
-    types::{DeviceInput, ImageFormat, ImageMirroring, ImageMode, ImageRotation},
+    types::{
+        DeviceInput, EncodingOptions, ImageFormat, ImageMirroring, ImageMode, ImageRotation,
+        ImageTransform, ProtocolVersion,
+    },
 };
-use std::{thread::sleep, time::Duration};
+use std::time::Duration;
 
 const QUERY: DeviceQuery = DeviceQuery::new(65440, 1, 0x0300, 0x1020);
 
@@ -13,8 +16,8 @@ const KEY_COUNT: u8 = 18;
 const IMAGE_FORMAT: ImageFormat = ImageFormat {
     mode: ImageMode::JPEG,
     size: (85, 85),
-    rotation: ImageRotation::Rot90,
-    mirror: ImageMirroring::Both,
+    transform: ImageTransform::new(ImageRotation::Rot90, ImageMirroring::Both),
+    encoding: EncodingOptions::Default,
 };
 
 /// Converts opendeck key index to device key index
@@ -46,49 +49,44 @@ async fn main() -> Result<(), MirajazzError> {
             "Connecting to {:04X}:{:04X}, {}",
             dev.vendor_id,
             dev.product_id,
-            dev.serial_number.clone().unwrap()
+            dev.serial_number.as_deref().unwrap_or("no serial")
         );
 
         // Connect to the device
-        let device = Device::connect(&dev, 1, KEY_COUNT as usize, 0).await?;
+        let options = ConnectOptions::new()
+            .protocol_version(ProtocolVersion::V1)
+            .keys(KEY_COUNT as usize)
+            .usage(65440, 1);
+
+        let device = Device::connect_with(&dev, options)
+            .await?
+            .with_boot_delay(Some(Duration::from_millis(50)));
 
         // Print out some info from the device
         println!(
             "Connected to '{}', fw: {:?}",
-            device.serial_number(),
-            device.firmware_version
+            device.id(),
+            device.firmware_version().await?
         );
 
-        device.set_brightness(50).await?;
-        device.clear_all_button_images().await?;
         // Use image-rs to load an image
         let image = open("examples/test.jpg").unwrap();
 
         println!("Key count: {}", device.key_count());
-        // Write it to the device
-        for i in 0..device.key_count() as u8 {
-            device
-                .set_button_image(opendeck_to_device(i), IMAGE_FORMAT, image.clone())
-                .await?;
-
-            sleep(Duration::from_millis(50));
+        // Write it to every key
+        let images: Vec<_> = (0..device.key_count() as u8)
+            .map(|i| (opendeck_to_device(i), image.clone()))
+            .collect();
 
-            // Flush
-            device.flush().await?;
-        }
+        device.show_splash(&images, IMAGE_FORMAT, 50).await?;
 
         let reader = device.get_reader(|key, _state| {
             println!("Key {}, converted {}", key, device_to_opendeck(key));
 
             Ok(DeviceInput::NoData)
-        });
-
-        loop {
-            match reader.read(None).await {
-                Ok(updates) => updates,
-                Err(_) => break,
-            };
-        }
+        })?;
+
+        while reader.read(None).await.is_ok() {}
 
         drop(reader);
 
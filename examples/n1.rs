@@ -1,14 +1,15 @@
 use image::open;
 use mirajazz::{
-    device::{list_devices, Device, DeviceQuery},
+    device::{Device, DeviceQuery},
     error::MirajazzError,
-    types::{DeviceInput, ImageFormat, ImageMirroring, ImageMode, ImageRotation},
+    types::{DeviceInput, EncodingOptions, ImageFormat, ImageMode, ImageTransform, Zone},
 };
 use std::{thread::sleep, time::Duration};
 
 const QUERY: DeviceQuery = DeviceQuery::new(65440, 1, 0x6603, 0x1000);
 
 #[repr(u8)]
+#[allow(dead_code)]
 enum N1Mode {
     Keyboard = 1,
     Calculator = 2,
@@ -17,76 +18,63 @@ enum N1Mode {
 
 const KEY_COUNT: u8 = 18;
 
-fn image_format_for_key(key: u8) -> ImageFormat {
-    if key >= 15 {
-        TOP_ROW_IMAGE_FORMAT
-    } else {
-        IMAGE_FORMAT
-    }
-}
-
 const IMAGE_FORMAT: ImageFormat = ImageFormat {
     mode: ImageMode::JPEG,
     size: (96, 96),
-    rotation: ImageRotation::Rot0,
-    mirror: ImageMirroring::None,
+    transform: ImageTransform::NONE,
+    encoding: EncodingOptions::Default,
 };
 
 const TOP_ROW_IMAGE_FORMAT: ImageFormat = ImageFormat {
     mode: ImageMode::JPEG,
     size: (64, 64),
-    rotation: ImageRotation::Rot0,
-    mirror: ImageMirroring::None,
+    transform: ImageTransform::NONE,
+    encoding: EncodingOptions::Default,
 };
 
+/// Main grid and the top row use different image sizes
+const ZONES: [Zone; 2] = [
+    Zone {
+        key_range: 0..15,
+        image_format: IMAGE_FORMAT,
+    },
+    Zone {
+        key_range: 15..KEY_COUNT,
+        image_format: TOP_ROW_IMAGE_FORMAT,
+    },
+];
+
 #[tokio::main]
 async fn main() -> Result<(), MirajazzError> {
     println!("Mirajazz example for MiraBox N1");
 
-    for dev in list_devices(&[QUERY]).await? {
-        println!(
-            "Connecting to {:04X}:{:04X}, {}",
-            dev.vendor_id,
-            dev.product_id,
-            dev.serial_number.clone().unwrap()
-        );
-
-        // Connect to the device
-        let device = Device::connect(&dev, 3, KEY_COUNT as usize, 0).await?;
+    // Connect to every matching device, key count comes from the known model
+    for device in Device::open_all(QUERY).await? {
+        let device = device.with_zones(ZONES.to_vec());
         device.set_mode(N1Mode::Software as u8).await?;
         sleep(Duration::from_millis(50));
 
         // Print out some info from the device
-        println!("Connected to '{}'", device.serial_number());
+        println!("Connected to '{}'", device.id());
 
-        device.set_brightness(50).await?;
-        device.clear_all_button_images().await?;
         // Use image-rs to load an image
         let image = open("examples/test.jpg").unwrap();
 
         println!("Key count: {}", device.key_count());
-        // Write it to the device
-        for i in 0..device.key_count() as u8 {
-            device
-                .set_button_image(i, image_format_for_key(i), image.clone())
-                .await?;
+        // Write it to every key, image format is picked from the key's zone
+        let images: Vec<_> = (0..device.key_count() as u8)
+            .map(|i| (i, image.clone()))
+            .collect();
 
-            // Flush
-            device.flush().await?;
-        }
+        device.show_splash(&images, IMAGE_FORMAT, 50).await?;
 
         let reader = device.get_reader(|key, state| {
             println!("Key {}: {state}", key);
 
             Ok(DeviceInput::NoData)
-        });
-
-        loop {
-            match reader.read(None).await {
-                Ok(updates) => updates,
-                Err(_) => break,
-            };
-        }
+        })?;
+
+        while reader.read(None).await.is_ok() {}
 
         drop(reader);
 
@@ -0,0 +1,53 @@
+use async_hid::HidBackend;
+use futures_lite::StreamExt;
+use mirajazz::{error::MirajazzError, probe::probe};
+use std::time::Duration;
+
+/// Vendor-defined usage page used by the devices
+const USAGE_PAGE: u16 = 65440;
+
+const LISTEN: Duration = Duration::from_secs(10);
+
+#[tokio::main]
+async fn main() -> Result<(), MirajazzError> {
+    println!("Mirajazz probe, only reads from devices, nothing is written");
+
+    let devices = HidBackend::default()
+        .enumerate()
+        .await?
+        .filter(|d| d.usage_page == USAGE_PAGE)
+        .collect::<Vec<_>>()
+        .await;
+
+    for dev in devices {
+        println!(
+            "\nFound {:04X}:{:04X} '{}', usage {}:{}, serial {:?}",
+            dev.vendor_id,
+            dev.product_id,
+            dev.name,
+            dev.usage_page,
+            dev.usage_id,
+            dev.serial_number
+        );
+        println!(
+            "Listening for {} seconds, press keys and twist encoders",
+            LISTEN.as_secs()
+        );
+
+        let report = probe(&dev, LISTEN).await?;
+
+        println!("Firmware report: {:02X?}", report.firmware_report);
+
+        for (at, input) in &report.input_reports {
+            println!(
+                "{:>6}ms {:02X?}",
+                at.as_millis(),
+                &input[..input.len().min(16)]
+            );
+        }
+
+        println!("\n{}", report.profile_skeleton());
+    }
+
+    Ok(())
+}
@@ -14,20 +14,17 @@ async fn main() -> Result<(), MirajazzError> {
 
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 3 {
-        eprintln!("Usage: cargo run --example read_firmware_version 0000 1111");
-        eprintln!("Where 0000 is a vendor_id, and 1111 is a product_id");
+    if args.len() < 2 {
+        eprintln!("Usage: cargo run --example read_firmware_version 0000:1111");
+        eprintln!("Where 0000 is a vendor_id, and 1111 is a product_id or *");
         exit(1);
     }
 
-    let vid = u16::from_str_radix(&args[1], 16).unwrap();
-    let pid = u16::from_str_radix(&args[2], 16).unwrap();
+    let query: DeviceQuery = args[1].parse()?;
+    let devices = list_devices(std::slice::from_ref(&query)).await?;
 
-    let query = DeviceQuery::new(65440, 1, vid, pid);
-    let devices = list_devices(&[query]).await?;
-
-    if devices.len() == 0 {
-        eprintln!("No connected devices with VID 0x{:X} PID 0x{:X}", vid, pid);
+    if devices.is_empty() {
+        eprintln!("No connected devices matching {}", query);
         exit(1);
     }
 
@@ -8,8 +8,9 @@ const QUERY: DeviceQuery = DeviceQuery::new(65440, 2, 0x0300, 0x1003);
 
 #[tokio::main]
 async fn main() -> Result<(), MirajazzError> {
+    let queries = [QUERY];
     let mut watcher_struct = DeviceWatcher::new();
-    let mut watcher = watcher_struct.watch(&[QUERY]).await?;
+    let mut watcher = watcher_struct.watch(&queries).await?;
 
     loop {
         if let Some(ev) = watcher.next().await {
@@ -0,0 +1,256 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter},
+    future::Future,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::{
+    device::Device,
+    error::MirajazzError,
+    protocol::{is_commit_ack, Command},
+    types::{ImageFormat, InputMode},
+};
+
+/// Settings of [Device::run_conformance]
+#[derive(Clone, Debug)]
+pub struct ConformanceOptions {
+    /// Key used for image steps, physical index. Image steps are skipped if [None]
+    pub test_key: Option<u8>,
+    /// Image format of the test key, uses zone of the key if device has zones
+    pub image_format: ImageFormat,
+    /// How long to wait for responses
+    pub timeout: Duration,
+}
+
+impl Default for ConformanceOptions {
+    fn default() -> Self {
+        Self {
+            test_key: None,
+            image_format: ImageFormat::default(),
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Outcome of a single conformance step
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StepOutcome {
+    /// Device did what was expected
+    Passed,
+    /// Device failed the step, with raw response if it sent one
+    Failed {
+        error: String,
+        response: Option<Vec<u8>>,
+    },
+    /// Device doesn't implement the step, or it was skipped
+    Unsupported,
+}
+
+/// Result of a single conformance step
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConformanceStep {
+    /// Name of the step, owned when report was deserialized
+    pub name: Cow<'static, str>,
+    /// Outcome of the step
+    pub outcome: StepOutcome,
+    /// How long the step took
+    pub took: Duration,
+}
+
+/// Which parts of the protocol the device implements, see [Device::run_conformance]
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConformanceReport {
+    /// Steps in the order they were run
+    pub steps: Vec<ConformanceStep>,
+}
+
+impl ConformanceReport {
+    /// Returns steps that failed
+    pub fn failed(&self) -> impl Iterator<Item = &ConformanceStep> {
+        self.steps
+            .iter()
+            .filter(|step| matches!(step.outcome, StepOutcome::Failed { .. }))
+    }
+
+    async fn run<F>(&mut self, name: &'static str, step: F)
+    where
+        F: Future<Output = StepOutcome>,
+    {
+        let started = Instant::now();
+        let outcome = step.await;
+
+        self.steps.push(ConformanceStep {
+            name: Cow::Borrowed(name),
+            outcome,
+            took: started.elapsed(),
+        });
+    }
+}
+
+impl Display for ConformanceReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for step in &self.steps {
+            match &step.outcome {
+                StepOutcome::Passed => writeln!(f, "{}: pass ({:?})", step.name, step.took)?,
+                StepOutcome::Unsupported => writeln!(f, "{}: unsupported", step.name)?,
+                StepOutcome::Failed { error, response } => {
+                    write!(f, "{}: FAIL ({:?}) {}", step.name, step.took, error)?;
+
+                    if let Some(response) = response {
+                        write!(f, ", response:")?;
+
+                        for byte in response.iter().take(32) {
+                            write!(f, " {:02x}", byte)?;
+                        }
+                    }
+
+                    writeln!(f)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Result<(), MirajazzError>> for StepOutcome {
+    fn from(result: Result<(), MirajazzError>) -> Self {
+        match result {
+            Ok(()) => StepOutcome::Passed,
+            Err(MirajazzError::UnsupportedOperation) => StepOutcome::Unsupported,
+            Err(err) => StepOutcome::Failed {
+                error: err.to_string(),
+                response: None,
+            },
+        }
+    }
+}
+
+impl Device {
+    /// Checks which parts of the protocol the device implements, for bringing up new devices
+    ///
+    /// Runs status query, firmware query, brightness, image write, clear, commit and keep-alive
+    /// steps, recording outcome and timing of each. Nothing is written to the device flash,
+    /// brightness and the image of the test key are restored afterwards where possible
+    pub async fn run_conformance(&self, options: ConformanceOptions) -> ConformanceReport {
+        let mut report = ConformanceReport::default();
+
+        report
+            .run("status query", async {
+                let request = match self.input_mode() {
+                    InputMode::Polled { request, .. } => request,
+                    InputMode::Push => return StepOutcome::Unsupported,
+                };
+
+                if let Err(err) = self.write_extended_data(request).await {
+                    return Err(err).into();
+                }
+
+                match self.read_report(options.timeout).await {
+                    Ok(Some(_)) => StepOutcome::Passed,
+                    Ok(None) => StepOutcome::Failed {
+                        error: "no response".to_string(),
+                        response: None,
+                    },
+                    Err(err) => Err(err).into(),
+                }
+            })
+            .await;
+
+        report
+            .run("firmware query", async {
+                match self.query_firmware_version().await {
+                    Ok(Some(_)) => StepOutcome::Passed,
+                    Ok(None) => StepOutcome::Unsupported,
+                    Err(err) => Err(err).into(),
+                }
+            })
+            .await;
+
+        let brightness = self.brightness().await;
+
+        report
+            .run("brightness", async {
+                let percent = brightness.map_or(100, |brightness| brightness.percent());
+
+                self.set_brightness(percent).await.into()
+            })
+            .await;
+
+        if let Some(key) = options.test_key {
+            let previous = self.get_button_image(key).await;
+
+            report
+                .run("image write", async {
+                    let image_format = match self.zone_for_key(key) {
+                        Some(zone) => zone.image_format,
+                        None => options.image_format,
+                    };
+
+                    match self.write_black_image(key, image_format).await {
+                        Ok(()) => self.flush_key(key).await.into(),
+                        Err(err) => Err(err).into(),
+                    }
+                })
+                .await;
+
+            report
+                .run("clear", async {
+                    let result = match self.initialize().await {
+                        Ok(()) => self.write_command(Command::ClearKey(key)).await,
+                        Err(err) => Err(err),
+                    };
+
+                    result.into()
+                })
+                .await;
+
+            if let Some(previous) = previous {
+                let restored = match self.write_image(key, &previous).await {
+                    Ok(()) => self.flush_key(key).await,
+                    Err(err) => Err(err),
+                };
+
+                if let Err(err) = restored {
+                    log::warn!("Failed to restore image of the test key: {}", err);
+                }
+            }
+        } else {
+            for name in ["image write", "clear"] {
+                report.run(name, async { StepOutcome::Unsupported }).await;
+            }
+        }
+
+        report
+            .run("commit", async {
+                if let Err(err) = self.write_command(Command::Commit).await {
+                    return Err(err).into();
+                }
+
+                match self.read_report(options.timeout).await {
+                    Ok(Some(response)) if is_commit_ack(&response) => StepOutcome::Passed,
+                    Ok(Some(response)) => StepOutcome::Failed {
+                        error: "unexpected response".to_string(),
+                        response: Some(response),
+                    },
+                    // Plenty of firmwares don't acknowledge commits
+                    Ok(None) => StepOutcome::Unsupported,
+                    Err(err) => Err(err).into(),
+                }
+            })
+            .await;
+
+        report
+            .run("keep-alive", async { self.keep_alive().await.into() })
+            .await;
+
+        report
+    }
+}
@@ -1,40 +1,175 @@
 use async_hid::{
-    AsyncHidWrite, Device as HidDevice, DeviceId, DeviceInfo as HidDeviceInfo, DeviceReader,
-    DeviceWriter, HidBackend,
+    AsyncHidWrite, Device as HidDevice, DeviceInfo as HidDeviceInfo, DeviceReader, DeviceWriter,
+    HidBackend,
 };
-use futures_lite::{Stream, StreamExt};
+use futures_lite::{future, stream, AsyncRead, AsyncReadExt, FutureExt, Stream, StreamExt};
 use image::DynamicImage;
+use log::warn;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{HashMap, HashSet, VecDeque},
     convert::identity,
+    fmt::{Display, Formatter},
+    str::FromStr,
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, PoisonError,
     },
+    time::{Duration, Instant, SystemTime},
 };
-use tokio::sync::Mutex;
+use tokio::{sync::Mutex, task, time};
 
 use crate::{
-    error::MirajazzError,
-    images::convert_image_with_format,
-    state::{DeviceState, DeviceStateReader},
-    types::{DeviceInput, DeviceLifecycleEvent, ImageFormat},
+    error::{is_busy_error, MirajazzError},
+    images::{
+        convert_image, convert_image_with_format, validate_image_data, ConversionMeta,
+        ConvertedImage,
+    },
+    kind::{Capabilities, Kind},
+    protocol::{
+        extend_payload, is_commit_ack, is_device_report, is_image_readback_header, is_noop_report,
+        is_transfer_nak, is_version_reply, legacy_packet_header, parse_firmware_version,
+        parse_model, parse_render_error, parse_version_reply, ChunkCollector, Command,
+        DeviceProtocol, ImageFraming, MiraboxProtocol, NAK,
+    },
+    retry::{self, RetryPolicy},
+    state::{
+        read_sized_report, AckMailbox, DeviceState, DeviceStateReader, InputParser, InputTransport,
+        OutputTransport, PendingRead, TransferLock, VirtualKeyState,
+    },
+    stats::{DeviceStats, SharedStats},
+    throttle::Throttle,
+    types::{
+        Brightness, ButtonBitmap, ChecksumKind, ClearStrategy, DeviceId, DeviceInput,
+        DeviceLifecycleEvent, DisplayOffFlush, EncoderMode, FlushReport, Geometry, HidDeviceId,
+        ImageFormat, ImageMirroring, ImageRotation, InputMode, KeyFlushOutcome, MirajazzDeviceInfo,
+        Orientation, PageSummary, ProtocolVersion, ReadPolicy, UploadCommand, VirtualKey, Zone,
+    },
 };
 
 /// Creates an instance of the async-hid backend
 ///
-/// Can be used if you don't want to link async-hid crate into your project
+/// Can be used if you don't want to link async-hid crate into your project. Pass it to
+/// [list_devices_with_backend] and [Device::connect_with_backend] to share one backend between them
 pub fn new_hid_backend() -> HidBackend {
     HidBackend::default()
 }
 
+/// How long to wait for the device to respond to a query
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Default minimal interval between brightness commands sent by [Device::set_brightness_throttled]
+const BRIGHTNESS_THROTTLE: Duration = Duration::from_millis(50);
+
+/// How long to wait for the device to accept or reject checksummed image transfer
+const CHECKSUM_ACK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How long to wait for the commit acknowledgment while negotiating packet size
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How many times image rejected because of checksum mismatch is sent again
+const CHECKSUM_RETRIES: usize = 2;
+
+/// Normalizes serial number reported by the backend, so the same device always has the same serial
+///
+/// Trims NULs and whitespace, serials made only of hex digits are uppercased
+pub fn normalize_serial(serial: &str) -> String {
+    let serial = serial.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+
+    if !serial.is_empty() && serial.chars().all(|c| c.is_ascii_hexdigit()) {
+        serial.to_ascii_uppercase()
+    } else {
+        serial.to_string()
+    }
+}
+
+/// Trims NULs and whitespace from descriptor string, [None] if nothing is left
+fn descriptor_string(string: &str) -> Option<String> {
+    let string = string.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+
+    if string.is_empty() {
+        None
+    } else {
+        Some(string.to_string())
+    }
+}
+
+/// Returns device info with normalized serial number
+fn normalize_info(mut info: HidDeviceInfo) -> HidDeviceInfo {
+    info.serial_number = info.serial_number.map(|serial| normalize_serial(&serial));
+    info
+}
+
+/// Converts protocol version passed to the legacy connect functions
+///
+/// Panics on unknown versions, same as passing them to [Device::connect] did before
+fn legacy_protocol_version(protocol_version: impl TryInto<ProtocolVersion>) -> ProtocolVersion {
+    match protocol_version.try_into() {
+        Ok(protocol_version) => protocol_version,
+        Err(_) => panic!("Maximum supported protocol version is 3"),
+    }
+}
+
+/// Returns ids of devices that match any of the queries
+///
+/// Ids are the same as [Device::id] of connected devices, as long as the model is known
+pub async fn list_device_ids(queries: &[DeviceQuery]) -> Result<Vec<DeviceId>, MirajazzError> {
+    let devices = list_devices(queries).await?;
+
+    Ok(devices
+        .iter()
+        .map(|device| DeviceId::from(&**device))
+        .collect())
+}
+
+/// Way of matching serial number of the device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialMatch {
+    /// Serial number must be exactly the same
+    Exact(String),
+    /// Serial number must start with provided string
+    Prefix(String),
+}
+
+impl SerialMatch {
+    /// Checks if serial number matches, both sides are compared normalized
+    ///
+    /// Prefixes are compared ignoring ASCII case, since a hex prefix of a serial that
+    /// isn't all hex is normalized differently
+    pub fn matches(&self, serial: &str) -> bool {
+        let serial = normalize_serial(serial);
+
+        match self {
+            SerialMatch::Exact(exact) => serial == normalize_serial(exact),
+            SerialMatch::Prefix(prefix) => {
+                let prefix = normalize_serial(prefix);
+
+                serial
+                    .get(..prefix.len())
+                    .is_some_and(|start| start.eq_ignore_ascii_case(&prefix))
+            }
+        }
+    }
+}
+
+/// Vendor-defined usage page of the interface known devices are controlled through
+pub const VENDOR_USAGE_PAGE: u16 = 65440;
+
+/// Usage id of the interface known devices are controlled through
+pub const VENDOR_USAGE_ID: u16 = 1;
+
 /// Struct for finding specific connected device
+///
+/// Can be parsed from strings like `0300:1003`, `6603:*` or `0300:1003@355499441494`,
+/// serial after `@` matches as a prefix, after `@=` exactly. Parsed queries use
+/// [VENDOR_USAGE_PAGE] and [VENDOR_USAGE_ID], and are displayed the same way
 #[derive(Debug, Clone)]
 pub struct DeviceQuery {
     usage_page: u16,
     usage_id: u16,
     vendor_id: u16,
-    product_id: u16,
+    /// [None] matches any product of the vendor
+    product_id: Option<u16>,
+    serial: Option<SerialMatch>,
 }
 
 impl DeviceQuery {
@@ -43,29 +178,171 @@ impl DeviceQuery {
             usage_page,
             usage_id,
             vendor_id,
-            product_id,
+            product_id: Some(product_id),
+            serial: None,
+        }
+    }
+
+    /// Returns builder of the query, with usage of the interface known devices use
+    pub fn builder(vendor_id: u16) -> DeviceQueryBuilder {
+        DeviceQueryBuilder {
+            query: Self {
+                usage_page: VENDOR_USAGE_PAGE,
+                usage_id: VENDOR_USAGE_ID,
+                vendor_id,
+                product_id: None,
+                serial: None,
+            },
+        }
+    }
+
+    /// Only matches devices with serial number matching provided one
+    ///
+    /// Devices without serial number never match such queries
+    pub fn with_serial(mut self, serial: SerialMatch) -> Self {
+        self.serial = Some(serial);
+        self
+    }
+
+    /// Checks if device matches the query
+    pub fn matches(&self, device: &HidDeviceInfo) -> bool {
+        if device.usage_page != self.usage_page
+            || device.usage_id != self.usage_id
+            || device.vendor_id != self.vendor_id
+            || self
+                .product_id
+                .is_some_and(|product_id| device.product_id != product_id)
+        {
+            return false;
+        }
+
+        match (&self.serial, &device.serial_number) {
+            (None, _) => true,
+            (Some(serial_match), Some(serial)) => serial_match.matches(serial),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// Builder of [DeviceQuery], see [DeviceQuery::builder]
+#[derive(Debug, Clone)]
+pub struct DeviceQueryBuilder {
+    query: DeviceQuery,
+}
+
+impl DeviceQueryBuilder {
+    /// Only matches devices with provided product id, any product of the vendor matches by default
+    pub fn product_id(mut self, product_id: u16) -> Self {
+        self.query.product_id = Some(product_id);
+        self
+    }
+
+    /// Overrides usage page and usage id of the interface
+    pub fn usage(mut self, usage_page: u16, usage_id: u16) -> Self {
+        self.query.usage_page = usage_page;
+        self.query.usage_id = usage_id;
+        self
+    }
+
+    /// Only matches devices with serial number matching provided one
+    pub fn serial(mut self, serial: SerialMatch) -> Self {
+        self.query.serial = Some(serial);
+        self
+    }
+
+    /// Builds the query
+    pub fn build(self) -> DeviceQuery {
+        self.query
+    }
+}
+
+impl FromStr for DeviceQuery {
+    type Err = MirajazzError;
+
+    fn from_str(query: &str) -> Result<Self, Self::Err> {
+        let (ids, serial) = match query.split_once('@') {
+            Some((ids, serial)) => (ids, Some(serial)),
+            None => (query, None),
+        };
+
+        let (vendor_id, product_id) = match ids.split_once(':') {
+            Some(parts) => parts,
+            None => {
+                return Err(MirajazzError::InvalidQuery(format!(
+                    "expected vid:pid, got '{}'",
+                    query
+                )))
+            }
+        };
+
+        let parse_id = |name: &str, id: &str| {
+            u16::from_str_radix(id.trim(), 16).map_err(|_| {
+                MirajazzError::InvalidQuery(format!("{} '{}' isn't a 16-bit hex number", name, id))
+            })
+        };
+
+        let mut builder = DeviceQuery::builder(parse_id("vendor id", vendor_id)?);
+
+        if product_id.trim() != "*" {
+            builder = builder.product_id(parse_id("product id", product_id)?);
+        }
+
+        match serial {
+            Some("") | Some("=") => {
+                return Err(MirajazzError::InvalidQuery(format!(
+                    "serial is empty in '{}'",
+                    query
+                )))
+            }
+            Some(serial) => {
+                builder = builder.serial(match serial.strip_prefix('=') {
+                    Some(exact) => SerialMatch::Exact(exact.to_string()),
+                    None => SerialMatch::Prefix(serial.to_string()),
+                })
+            }
+            None => {}
+        }
+
+        Ok(builder.build())
+    }
+}
+
+impl Display for DeviceQuery {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04x}:", self.vendor_id)?;
+
+        match self.product_id {
+            Some(product_id) => write!(f, "{:04x}", product_id)?,
+            None => write!(f, "*")?,
+        }
+
+        match &self.serial {
+            Some(SerialMatch::Prefix(prefix)) => write!(f, "@{}", prefix),
+            Some(SerialMatch::Exact(exact)) => write!(f, "@={}", exact),
+            None => Ok(()),
         }
     }
 }
 
 fn check_device(device: HidDevice, queries: &[DeviceQuery]) -> Option<HidDevice> {
-    if !queries.iter().any(|query| {
-        device.matches(
-            query.usage_page,
-            query.usage_id,
-            query.vendor_id,
-            query.product_id,
-        )
-    }) {
+    if !queries.iter().any(|query| query.matches(&device)) {
         return None;
     }
 
     Some(device)
 }
 
-/// Returns a list of devices as (Kind, Serial Number) that could be found using hid backend.
+/// Returns devices matching any of the queries, see [DeviceList] for polling repeatedly
 pub async fn list_devices(queries: &[DeviceQuery]) -> Result<HashSet<HidDevice>, MirajazzError> {
-    let devices = HidBackend::default()
+    list_devices_with_backend(&HidBackend::default(), queries).await
+}
+
+/// Same as [list_devices], but uses provided backend instead of creating its own
+pub async fn list_devices_with_backend(
+    backend: &HidBackend,
+    queries: &[DeviceQuery],
+) -> Result<HashSet<HidDevice>, MirajazzError> {
+    let devices = backend
         .enumerate()
         .await?
         .filter_map(|d| check_device(d, queries))
@@ -75,10 +352,377 @@ pub async fn list_devices(queries: &[DeviceQuery]) -> Result<HashSet<HidDevice>,
     Ok(devices)
 }
 
+/// Devices that appeared and disappeared since the previous [DeviceList::refresh]
+#[derive(Clone, Debug, Default)]
+pub struct DeviceListChanges {
+    /// Devices that weren't listed before
+    pub added: Vec<HidDeviceInfo>,
+    /// Devices that are no longer listed
+    pub removed: Vec<HidDeviceInfo>,
+}
+
+impl DeviceListChanges {
+    /// Returns true if nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Cached list of matching devices, for polling without creating a new backend every time
+///
+/// Devices can be connected with [Device::connect_with_backend], passing [DeviceList::backend]
+pub struct DeviceList {
+    backend: HidBackend,
+    queries: Vec<DeviceQuery>,
+    devices: HashSet<HidDevice>,
+}
+
+impl DeviceList {
+    /// Creates empty list of devices matching any of the queries, call [DeviceList::refresh] to fill it
+    pub fn new(queries: &[DeviceQuery]) -> Self {
+        DeviceList::with_backend(HidBackend::default(), queries)
+    }
+
+    /// Same as [DeviceList::new], but uses provided backend instead of creating its own
+    pub fn with_backend(backend: HidBackend, queries: &[DeviceQuery]) -> Self {
+        Self {
+            backend,
+            queries: queries.to_vec(),
+            devices: HashSet::new(),
+        }
+    }
+
+    /// Enumerates devices again, returning what changed since the previous refresh
+    pub async fn refresh(&mut self) -> Result<DeviceListChanges, MirajazzError> {
+        let devices = list_devices_with_backend(&self.backend, &self.queries).await?;
+
+        let changes = DeviceListChanges {
+            added: devices
+                .difference(&self.devices)
+                .map(|device| (**device).clone())
+                .collect(),
+            removed: self
+                .devices
+                .difference(&devices)
+                .map(|device| (**device).clone())
+                .collect(),
+        };
+
+        self.devices = devices;
+
+        Ok(changes)
+    }
+
+    /// Returns devices found by the last refresh
+    pub fn devices(&self) -> &HashSet<HidDevice> {
+        &self.devices
+    }
+
+    /// Returns backend used for enumeration
+    pub fn backend(&self) -> &HidBackend {
+        &self.backend
+    }
+}
+
+/// Checks done when connecting to the device
+#[derive(Copy, Clone, Debug)]
+struct ConnectChecks {
+    /// Fail if device is already connected by this process
+    exclusive: bool,
+    /// Fail if configuration doesn't match the known model of the device
+    match_kind: bool,
+}
+
+/// Configuration of the device to connect to, see [Device::connect_with]
+///
+/// Defaults to protocol version 1 without keys or encoders, so set at least the key count
+#[derive(Clone, Debug)]
+pub struct ConnectOptions {
+    protocol_version: ProtocolVersion,
+    key_count: usize,
+    encoder_count: usize,
+    checks: ConnectChecks,
+    /// Usage page and usage id of the interface to open, [None] opens any interface
+    usage: Option<(u16, u16)>,
+    /// How failed connection attempts are retried
+    retry: RetryPolicy,
+    /// How long to wait for the device to start answering after connecting, [None] to not wait
+    wait_ready: Option<Duration>,
+    /// Whether device reports both states of keys and encoders, [None] to use protocol defaults
+    both_states: Option<(bool, bool)>,
+    /// Packets sent after the built-in initialization ones
+    extra_init: Vec<Vec<u8>>,
+}
+
+impl ConnectOptions {
+    /// Creates default options
+    pub fn new() -> Self {
+        ConnectOptions {
+            protocol_version: ProtocolVersion::V1,
+            key_count: 0,
+            encoder_count: 0,
+            checks: ConnectChecks {
+                exclusive: true,
+                match_kind: true,
+            },
+            usage: None,
+            retry: RetryPolicy::never(),
+            wait_ready: None,
+            both_states: None,
+            extra_init: Vec::new(),
+        }
+    }
+
+    /// Sets protocol version of the device
+    pub fn protocol_version(mut self, protocol_version: ProtocolVersion) -> Self {
+        self.protocol_version = protocol_version;
+        self
+    }
+
+    /// Sets number of keys
+    pub fn keys(mut self, key_count: usize) -> Self {
+        self.key_count = key_count;
+        self
+    }
+
+    /// Sets number of encoders
+    pub fn encoders(mut self, encoder_count: usize) -> Self {
+        self.encoder_count = encoder_count;
+        self
+    }
+
+    /// Only opens the interface with provided usage page and usage id, for devices exposing
+    /// several collections in different order on different platforms, like [VENDOR_USAGE_PAGE]
+    /// with usages 1 and 2
+    ///
+    /// Connecting returns [MirajazzError::InvalidDeviceError] if no such interface exists
+    pub fn usage(mut self, usage_page: u16, usage_id: u16) -> Self {
+        self.usage = Some((usage_page, usage_id));
+        self
+    }
+
+    /// Retries connecting according to the policy, looking the device up again on every attempt,
+    /// for connecting right after the device was plugged in, see [RetryPolicy::plug_in]
+    ///
+    /// Doesn't apply to [Device::connect_from_device], which opens the provided handle only once
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Waits up to `max` for the device to start answering after connecting,
+    /// see [Device::wait_until_ready]
+    ///
+    /// Connecting returns [MirajazzError::NotReady] if device didn't answer in time
+    pub fn wait_ready(mut self, max: Duration) -> Self {
+        self.wait_ready = Some(max);
+        self
+    }
+
+    /// Sets whether device reports both pressed and released states of keys and encoders,
+    /// see [Device::with_supports_both_keypress_states] and [Device::with_supports_both_encoder_states]
+    ///
+    /// Defaults to what protocol version of the device supports
+    pub fn both_states(mut self, keypress: bool, encoder: bool) -> Self {
+        self.both_states = Some((keypress, encoder));
+        self
+    }
+
+    /// Sets packets sent after the built-in initialization ones, see [Device::with_extra_init]
+    ///
+    /// Packets are set before waiting for [ConnectOptions::wait_ready], so the device is
+    /// initialized with them from the start
+    pub fn extra_init(mut self, packets: Vec<Vec<u8>>) -> Self {
+        self.extra_init = packets;
+        self
+    }
+
+    /// Allows connecting to the device already connected by this process
+    ///
+    /// **NOTE:** Multiple handles will compete for the input reports
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.checks.exclusive = !shared;
+        self
+    }
+
+    /// Allows configuration that doesn't match the known model of the device, for lookalike clones
+    pub fn allow_mismatch(mut self, allow: bool) -> Self {
+        self.checks.match_kind = !allow;
+        self
+    }
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        ConnectOptions::new()
+    }
+}
+
+impl From<&Kind> for ConnectOptions {
+    fn from(kind: &Kind) -> Self {
+        ConnectOptions::new()
+            .protocol_version(kind.protocol_version)
+            .keys(kind.key_count)
+            .encoders(kind.encoder_count)
+    }
+}
+
+/// Checks that provided configuration matches the known model
+fn check_kind(
+    device: &DeviceId,
+    kind: &Kind,
+    protocol_version: ProtocolVersion,
+    key_count: usize,
+    encoder_count: usize,
+) -> Result<(), MirajazzError> {
+    let expected = (kind.protocol_version, kind.key_count, kind.encoder_count);
+    let got = (protocol_version, key_count, encoder_count);
+
+    if expected != got {
+        let describe = |(protocol_version, key_count, encoder_count)| {
+            format!(
+                "{:?} with {} keys and {} encoders",
+                protocol_version, key_count, encoder_count
+            )
+        };
+
+        return Err(MirajazzError::ConfigMismatch {
+            device: device.clone(),
+            expected: format!("{} ({})", kind.name, describe(expected)),
+            got: describe(got),
+        });
+    }
+
+    Ok(())
+}
+
+/// Rotates image so it appears upright on the device rotated to provided orientation
+fn upright(image: DynamicImage, orientation: Orientation) -> DynamicImage {
+    match orientation {
+        Orientation::Normal => image,
+        Orientation::Rot90 => image.rotate270(),
+        Orientation::Rot180 => image.rotate180(),
+        Orientation::Rot270 => image.rotate90(),
+    }
+}
+
+/// Reverts [upright]
+fn unrotate(image: DynamicImage, orientation: Orientation) -> DynamicImage {
+    match orientation {
+        Orientation::Normal => image,
+        Orientation::Rot90 => image.rotate90(),
+        Orientation::Rot180 => image.rotate180(),
+        Orientation::Rot270 => image.rotate270(),
+    }
+}
+
+/// Reverts rotation and mirroring of the image format, for images decoded back from image data
+fn undo_transform(image: DynamicImage, image_format: ImageFormat) -> DynamicImage {
+    let image = match image_format.transform.mirror {
+        ImageMirroring::None => image,
+        ImageMirroring::X => image.fliph(),
+        ImageMirroring::Y => image.flipv(),
+        ImageMirroring::Both => image.fliph().flipv(),
+    };
+
+    match image_format.transform.rotation {
+        ImageRotation::Rot0 => image,
+        ImageRotation::Rot90 => image.rotate270(),
+        ImageRotation::Rot180 => image.rotate180(),
+        ImageRotation::Rot270 => image.rotate90(),
+    }
+}
+
+/// State of the device that can be reapplied after reconnecting, see [Device::snapshot]
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSnapshot {
+    /// Last brightness set on the device
+    pub brightness: Option<Brightness>,
+    /// Rotation of the device
+    pub orientation: Orientation,
+    /// Displayed images by physical key index
+    pub images: Vec<(u8, Arc<[u8]>)>,
+}
+
+/// Secondary interface of the device on the same usage page, opened with [Device::open_aux_interface]
+struct AuxInterface {
+    /// Device of the interface, used for feature reports
+    device: HidDevice,
+    /// Reader of the interface
+    reader: Mutex<DeviceReader>,
+    /// Writer of the interface
+    writer: Mutex<DeviceWriter>,
+}
+
+/// Devices currently connected by this process, by platform path
+static OPEN_DEVICES: std::sync::Mutex<Vec<(HidDeviceId, DeviceId)>> =
+    std::sync::Mutex::new(Vec::new());
+
+/// Entry in the list of connected devices, removed from the list when dropped
+struct OpenDeviceRegistration(HidDeviceId);
+
+impl OpenDeviceRegistration {
+    fn register(hid_id: &HidDeviceId, id: &DeviceId) -> Result<Self, MirajazzError> {
+        let mut open = OPEN_DEVICES.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if open.iter().any(|(open_id, _)| open_id == hid_id) {
+            return Err(MirajazzError::AlreadyConnected(id.clone()));
+        }
+
+        open.push((hid_id.clone(), id.clone()));
+
+        Ok(Self(hid_id.clone()))
+    }
+}
+
+impl Drop for OpenDeviceRegistration {
+    fn drop(&mut self) {
+        OPEN_DEVICES
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(hid_id, _)| *hid_id != self.0);
+    }
+}
+
+/// Returns ids of devices that are currently connected by this process
+///
+/// Devices connected with [ConnectOptions::shared] handles are not included
+pub fn open_devices() -> Vec<DeviceId> {
+    OPEN_DEVICES
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|(_, id)| id.clone())
+        .collect()
+}
+
+/// Checks if the device that appeared on the same platform path is likely the same device
+/// that disconnected before, even though its serial number is missing or different
+///
+/// Some devices come back with an empty serial after deep sleep, until the next real reboot
+pub fn is_reconnect_candidate(previous: &HidDeviceInfo, current: &HidDeviceInfo) -> bool {
+    previous.id == current.id
+        && previous.vendor_id == current.vendor_id
+        && previous.product_id == current.product_id
+}
+
 pub struct DeviceWatcher {
     initialized: bool,
-    id_map: Arc<Mutex<HashMap<DeviceId, HidDeviceInfo>>>,
+    fuzzy_reconnect: bool,
+    id_map: Arc<Mutex<HashMap<HidDeviceId, HidDeviceInfo>>>,
     connected: Arc<Mutex<HashSet<HidDeviceInfo>>>,
+    /// Devices that disconnected, by platform path, used for fuzzy reconnect
+    departed: Arc<Mutex<HashMap<HidDeviceId, HidDeviceInfo>>>,
+    /// Number of appeared devices that failed to be queried and were skipped
+    query_failures: AtomicU64,
+    /// How long device has to stay present or absent before the event is emitted
+    settle_delay: Duration,
+}
+
+impl Default for DeviceWatcher {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DeviceWatcher {
@@ -86,11 +730,42 @@ impl DeviceWatcher {
     pub fn new() -> Self {
         Self {
             initialized: false,
+            fuzzy_reconnect: false,
             id_map: Arc::new(Mutex::new(HashMap::new())),
             connected: Arc::new(Mutex::new(HashSet::new())),
+            departed: Arc::new(Mutex::new(HashMap::new())),
+            query_failures: AtomicU64::new(0),
+            settle_delay: Duration::ZERO,
         }
     }
 
+    /// Returns number of appeared devices that failed to be queried and were skipped
+    ///
+    /// Platform backend can fail on unrelated broken devices, they don't stop the watcher
+    pub fn query_failures(&self) -> u64 {
+        self.query_failures.load(Ordering::Relaxed)
+    }
+
+    /// Reports device that came back on the same platform path with a missing or different serial
+    /// as [DeviceLifecycleEvent::Reconnected] instead of a new device
+    ///
+    /// This is a heuristic, platform paths can be reused by other devices with the same vid/pid
+    pub fn with_fuzzy_reconnect(mut self, fuzzy: bool) -> Self {
+        self.fuzzy_reconnect = fuzzy;
+        self
+    }
+
+    /// Delays events until the device stayed connected or disconnected for the settle delay,
+    /// for hubs that renegotiate a couple of times on plug-in
+    ///
+    /// Connection and disconnection of the same device within the delay cancel each other out.
+    /// Events are emitted in the order they happened, so an event waiting to settle holds back
+    /// the following events of other devices
+    pub fn with_settle_delay(mut self, delay: Duration) -> Self {
+        self.settle_delay = delay;
+        self
+    }
+
     /// Returns [Stream] of device connect/disconnect events
     ///
     /// **NOTE:** Only watches new events, to get already connected devices, use [list_devices]
@@ -121,8 +796,10 @@ impl DeviceWatcher {
         let mut connected = self.connected.lock().await;
 
         for (id, device) in already_connected.into_iter() {
-            map.insert(id, device.clone());
-            connected.insert(device.clone());
+            let info = normalize_info(device.clone());
+
+            map.insert(id, info.clone());
+            connected.insert(info);
         }
 
         drop(map);
@@ -133,19 +810,43 @@ impl DeviceWatcher {
             .then(|e| async {
                 match e {
                     async_hid::DeviceEvent::Connected(device_id) => {
-                        let device = HidBackend::default()
-                            .query_devices(&device_id)
-                            .await
-                            .unwrap()
-                            .filter_map(|d| check_device(d, queries))
-                            .last()?;
+                        let backend = HidBackend::default();
+
+                        let devices = match backend.query_devices(&device_id).await {
+                            Ok(devices) => devices,
+                            Err(err) => {
+                                warn!("Skipping device that failed to be queried: {}", err);
+                                self.query_failures.fetch_add(1, Ordering::Relaxed);
 
-                        let info = device.clone();
+                                return None;
+                            }
+                        };
+
+                        let device = devices.filter_map(|d| check_device(d, queries)).last()?;
+
+                        let info = normalize_info(device.clone());
                         drop(device);
 
-                        self.id_map.lock().await.insert(device_id, info.clone());
+                        self.id_map
+                            .lock()
+                            .await
+                            .insert(device_id.clone(), info.clone());
                         let new = self.connected.lock().await.insert(info.clone());
 
+                        let previous = self.departed.lock().await.remove(&device_id);
+
+                        if let Some(previous) = previous {
+                            if new
+                                && previous.serial_number != info.serial_number
+                                && is_reconnect_candidate(&previous, &info)
+                            {
+                                return Some(DeviceLifecycleEvent::Reconnected {
+                                    previous,
+                                    current: info,
+                                });
+                            }
+                        }
+
                         if new {
                             Some(DeviceLifecycleEvent::Connected(info))
                         } else {
@@ -156,6 +857,10 @@ impl DeviceWatcher {
                         let info = self.id_map.lock().await.remove(&device_id)?;
                         let existed = self.connected.lock().await.remove(&info);
 
+                        if existed && self.fuzzy_reconnect {
+                            self.departed.lock().await.insert(device_id, info.clone());
+                        }
+
                         if existed {
                             Some(DeviceLifecycleEvent::Disconnected(info))
                         } else {
@@ -166,69 +871,460 @@ impl DeviceWatcher {
             })
             .filter_map(identity);
 
-        Ok(Box::pin(watcher))
+        Ok(settle(Box::pin(watcher), self.settle_delay))
     }
 }
 
+/// Holds events back for the settle delay, dropping connections and disconnections
+/// that were undone within it
+fn settle<S>(events: S, delay: Duration) -> impl Stream<Item = DeviceLifecycleEvent> + Send + Unpin
+where
+    S: Stream<Item = DeviceLifecycleEvent> + Send + Unpin,
+{
+    let state = (
+        Some(events),
+        VecDeque::<(DeviceLifecycleEvent, time::Instant)>::new(),
+    );
+
+    Box::pin(stream::unfold(
+        state,
+        move |(mut events, mut pending)| async move {
+            loop {
+                let deadline = pending.front().map(|(_, deadline)| *deadline);
+
+                if deadline.is_some_and(|deadline| deadline <= time::Instant::now()) {
+                    if let Some((event, _)) = pending.pop_front() {
+                        return Some((event, (events, pending)));
+                    }
+                }
+
+                let source = match (events.as_mut(), deadline) {
+                    (Some(source), _) => source,
+                    (None, Some(deadline)) => {
+                        time::sleep_until(deadline).await;
+                        continue;
+                    }
+                    (None, None) => return None,
+                };
+
+                let next = async { Some(source.next().await) }
+                    .or(async {
+                        match deadline {
+                            Some(deadline) => time::sleep_until(deadline).await,
+                            None => future::pending().await,
+                        }
+
+                        None
+                    })
+                    .await;
+
+                let event = match next {
+                    Some(Some(event)) => event,
+                    Some(None) => {
+                        events = None;
+                        continue;
+                    }
+                    None => continue,
+                };
+
+                let device_id = event.device_id();
+
+                let undone = pending.iter().position(|(waiting, _)| {
+                    waiting.device_id() == device_id
+                        && matches!(
+                            (waiting, &event),
+                            (
+                                DeviceLifecycleEvent::Connected(_),
+                                DeviceLifecycleEvent::Disconnected(_)
+                            ) | (
+                                DeviceLifecycleEvent::Disconnected(_),
+                                DeviceLifecycleEvent::Connected(_)
+                            )
+                        )
+                });
+
+                match undone {
+                    Some(index) => {
+                        pending.remove(index);
+                    }
+                    None => pending.push_back((event, time::Instant::now() + delay)),
+                }
+            }
+        },
+    ))
+}
+
 /// Interface for a device
 pub struct Device {
+    /// Identity of the device
+    id: DeviceId,
+    /// Platform identifier of the device
+    hid_id: HidDeviceId,
     /// Vendor ID of the device
     pub vid: u16,
     /// Product ID of the device
     pub pid: u16,
-    /// Serial number
-    pub serial_number: String,
+    /// Serial number, [None] if platform backend didn't report it
+    pub serial_number: Option<String>,
+    /// Product string of the descriptor
+    product: Option<String>,
+    /// Manufacturer string of the descriptor
+    manufacturer: Option<String>,
     /// Firmware version
     pub firmware_version: Option<String>,
+    /// Firmware version string, for [Device::firmware_version]
+    parsed_firmware_version: Mutex<Option<String>>,
+    /// Model reported by the firmware, if present
+    model: Option<String>,
     /// Protocol version
-    protocol_version: usize,
+    protocol_version: ProtocolVersion,
+    /// Encoding of commands
+    protocol: Arc<dyn DeviceProtocol>,
+    /// Packets sent after the built-in initialization ones
+    extra_init: Vec<Vec<u8>>,
     /// Whether the device is capable of reporting ButtonUp
     supports_both_keypress_states: bool,
     /// Whether the device is capable of reporting EncoderUp
     supports_both_encoder_states: bool,
+    /// Opcode of the image read-back command, if device supports it
+    image_readback_command: Option<[u8; 3]>,
+    /// Opcode of the firmware version query sent as output report, if device supports it
+    version_query_command: Option<[u8; 3]>,
+    /// Command for uploading the standby image, if device supports it
+    standby_image_command: Option<UploadCommand>,
     /// Number of keys
     key_count: usize,
     /// Number of encoders
     encoder_count: usize,
-    /// Packet size
-    packet_size: usize,
+    /// Groups of keys with different image formats
+    zones: Vec<Zone>,
+    /// Native resolution of key images, after rotation
+    native_image_size: Option<(usize, usize)>,
+    /// Physical arrangement of keys and encoders
+    geometry: Option<Geometry>,
+    /// Features the firmware supports, methods using others return an error without sending anything
+    capabilities: Capabilities,
+    /// Rotation of the device, set by [Device::set_orientation]
+    orientation: std::sync::Mutex<Orientation>,
+    /// Logical index of every physical key, shared with readers, empty if layout isn't rotated
+    logical_keys: Arc<std::sync::RwLock<Vec<u8>>>,
+    /// Rescale images that don't match native resolution instead of returning an error
+    auto_adapt_images: bool,
+    /// Packet size, shared with readers
+    packet_size: Arc<AtomicUsize>,
+    /// Probe alternate packet sizes during the handshake
+    negotiate_packet_size: bool,
+    /// Input report size
+    input_report_size: usize,
     /// Device reader
-    reader: Arc<Mutex<DeviceReader>>,
+    reader: Arc<Mutex<InputTransport>>,
     /// Device writer
-    writer: Arc<Mutex<DeviceWriter>>,
+    writer: Arc<Mutex<OutputTransport>>,
+    /// Held while sending multi-packet transfers, so nothing gets in between the packets
+    transfer_lock: Arc<TransferLock>,
+    /// How the device delivers input reports
+    input_mode: InputMode,
+    /// What readers do with malformed input reports
+    read_policy: ReadPolicy,
+    /// How readers interpret encoder inputs
+    encoder_mode: EncoderMode,
     /// Temporarily cache the image before sending it to the device
-    image_cache: Mutex<HashMap<u8, Vec<u8>>>,
-    /// Device needs to be initialized
+    image_cache: Mutex<HashMap<u8, Arc<[u8]>>>,
+    /// Images that were flushed to the device and are currently displayed
+    remembered_images: Mutex<HashMap<u8, Arc<[u8]>>>,
+    /// Per-key outcomes of the last flush
+    last_flush_report: Mutex<FlushReport>,
+    /// Last brightness set on the device
+    brightness: Mutex<Option<Brightness>>,
+    /// Coalesces brightness commands sent by [Device::set_brightness_throttled]
+    brightness_throttle: Throttle,
+    /// Whether the device forgets displayed images while sleeping
+    clears_on_sleep: bool,
+    /// How buttons are cleared
+    clear_strategy: ClearStrategy,
+    /// Display was turned off with [Device::display_off]
+    display_off: AtomicBool,
+    /// What flushes do while the display is turned off
+    display_off_flush: DisplayOffFlush,
+    /// Device needs a commit after turning display back on
+    display_on_commit: bool,
+    /// How long the device needs to finish booting after being connected
+    boot_delay: Option<Duration>,
+    /// Device needs the handshake, required before any command
+    handshaken: AtomicBool,
+    /// Device display needs to be initialized, required before display commands
     initialized: AtomicBool,
+    /// Held while performing the handshake or initialization, so they happen once
+    init_lock: Mutex<()>,
+    /// Statistics of device operations
+    stats: SharedStats,
+    /// How long to wait for the commit acknowledgment, [None] to not wait
+    commit_ack_timeout: Option<Duration>,
+    /// How long to wait for acknowledgment of image transfers and commits, failing without it
+    ack_timeout: Option<Duration>,
+    /// Input reports received while waiting for responses, shared with readers
+    queued_reports: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    /// Replies to writes received by readers, shared with them
+    acks: Arc<AckMailbox>,
+    /// Read left unfinished by non-blocking reads, shared with readers
+    pending_read: Arc<PendingRead>,
+    /// Send the affected key again when device reports a render error during commit
+    resend_on_render_error: bool,
+    /// Checksum of image data sent in the transfer header
+    image_checksum: Option<ChecksumKind>,
+    /// How operations failing with transient errors are retried
+    retry_policy: RetryPolicy,
+    /// Key index of every raw key code reported by the device, empty to pass codes as is
+    key_codes: HashMap<u8, u8>,
+    /// What the conversion did to pending images
+    conversions: Mutex<HashMap<u8, ConversionMeta>>,
+    /// Readers report input classes seen for the first time
+    capability_events: bool,
+    /// Keys readers report beyond the physical ones
+    virtual_keys: Vec<VirtualKey>,
+    /// Secondary interface, if opened
+    aux: Mutex<Option<Arc<AuxInterface>>>,
+    /// Prefer secondary interface for status queries when it's open
+    aux_queries: bool,
+    /// Entry in the list of connected devices, if device was connected exclusively
+    _registration: Option<OpenDeviceRegistration>,
 }
 
 /// Static functions of the struct
 impl Device {
-    /// Attempts to connect to the device
-    pub async fn connect(
-        dev: &HidDeviceInfo,
-        protocol_version: usize,
-        key_count: usize,
-        encoder_count: usize,
-    ) -> Result<Device, MirajazzError> {
-        assert!(
-            protocol_version != 0,
-            "Use protocol_version 1 instead of 0. Protocol version 0 will be set automatically when needed"
-        );
-
-        assert!(
-            protocol_version <= 3,
-            "Maximum supported protocol version is 3"
-        );
+    /// Connects to the first device of a known [Kind] matching the query, preferring devices
+    /// with serial numbers. Key and encoder counts and protocol version are taken from the [Kind]
+    ///
+    /// Returns [MirajazzError::NoKnownDevice] listing matching devices if none is of a known model
+    pub async fn open_first(query: DeviceQuery) -> Result<Device, MirajazzError> {
+        let (known, found) = Device::find_known(query).await?;
 
-        let device = HidBackend::default().query_devices(&dev.id).await?.last();
+        match known.into_iter().next() {
+            Some((device, kind)) => Device::connect_from_device(device, kind.into()).await,
+            None => Err(MirajazzError::NoKnownDevice { found }),
+        }
+    }
 
-        let device = match device {
-            Some(device) => device,
-            None => return Err(MirajazzError::DeviceNotFoundError),
+    /// Connects to every device of a known [Kind] matching the query, see [Device::open_first]
+    ///
+    /// Devices that fail to connect are skipped with a warning
+    pub async fn open_all(query: DeviceQuery) -> Result<Vec<Device>, MirajazzError> {
+        let (known, found) = Device::find_known(query).await?;
+
+        if known.is_empty() {
+            return Err(MirajazzError::NoKnownDevice { found });
+        }
+
+        let mut devices = Vec::with_capacity(known.len());
+
+        for (device, kind) in known {
+            match Device::connect_from_device(device, kind.into()).await {
+                Ok(device) => devices.push(device),
+                Err(err) => warn!("Skipping {}: {}", kind.name, err),
+            }
+        }
+
+        Ok(devices)
+    }
+
+    /// Lists matching devices of known kinds, devices with serial numbers first,
+    /// along with `vid:pid` of every matching device
+    async fn find_known(
+        query: DeviceQuery,
+    ) -> Result<(Vec<(HidDevice, &'static Kind)>, Vec<String>), MirajazzError> {
+        let mut devices: Vec<HidDevice> = list_devices(&[query]).await?.into_iter().collect();
+
+        // Sorted for picking the same device every time
+        devices.sort_by(|a, b| {
+            (a.serial_number.is_none(), &a.serial_number, a.product_id).cmp(&(
+                b.serial_number.is_none(),
+                &b.serial_number,
+                b.product_id,
+            ))
+        });
+
+        let found = devices
+            .iter()
+            .map(|info| format!("{:04x}:{:04x}", info.vendor_id, info.product_id))
+            .collect();
+
+        let known = devices
+            .into_iter()
+            .filter_map(|info| {
+                let kind = Kind::from_vid_pid(info.vendor_id, info.product_id)?;
+
+                Some((info, kind))
+            })
+            .collect();
+
+        Ok((known, found))
+    }
+
+    /// Attempts to connect to the device
+    ///
+    /// Returns [MirajazzError::AlreadyConnected] if the device is already connected by this process,
+    /// unless options allow [ConnectOptions::shared] handles
+    ///
+    /// Returns [MirajazzError::ConfigMismatch] if the device is a known [Kind], and provided
+    /// configuration doesn't match it, unless options [ConnectOptions::allow_mismatch]
+    pub async fn connect_with(
+        dev: &HidDeviceInfo,
+        options: ConnectOptions,
+    ) -> Result<Device, MirajazzError> {
+        Device::connect_with_backend(&HidBackend::default(), dev, options).await
+    }
+
+    /// Same as [Device::connect_with], but uses provided backend instead of creating its own,
+    /// so apps managing several devices can share one backend for listing and connecting
+    pub async fn connect_with_backend(
+        backend: &HidBackend,
+        dev: &HidDeviceInfo,
+        options: ConnectOptions,
+    ) -> Result<Device, MirajazzError> {
+        retry::with_policy(&options.retry, || {
+            Device::query_and_connect(backend, dev, options.clone())
+        })
+        .await
+    }
+
+    async fn query_and_connect(
+        backend: &HidBackend,
+        dev: &HidDeviceInfo,
+        options: ConnectOptions,
+    ) -> Result<Device, MirajazzError> {
+        let mut devices = backend.query_devices(&dev.id).await?.peekable();
+
+        if devices.peek().is_none() {
+            return Err(MirajazzError::DeviceNotFoundError);
+        }
+
+        let device = match options.usage {
+            Some(usage) => devices.find(|device| (device.usage_page, device.usage_id) == usage),
+            None => devices.last(),
         };
 
-        let firmware_version = Device::read_firmware_version_from_raw_device(&device).await?;
+        match device {
+            Some(device) => Device::connect_from_device(device, options).await,
+            None => Err(MirajazzError::InvalidDeviceError),
+        }
+    }
+
+    /// Same as [Device::connect_with], but opens the exact device returned by [list_devices]
+    /// instead of looking it up again
+    ///
+    /// Tells apart identical devices sharing a serial, since the device handle refers
+    /// to a concrete interface
+    pub async fn connect_from_device(
+        device: HidDevice,
+        options: ConnectOptions,
+    ) -> Result<Device, MirajazzError> {
+        if options
+            .usage
+            .is_some_and(|usage| (device.usage_page, device.usage_id) != usage)
+        {
+            return Err(MirajazzError::InvalidDeviceError);
+        }
+
+        let device = Device::connect_device(
+            device,
+            options.protocol_version,
+            options.key_count,
+            options.encoder_count,
+            options.checks,
+        )
+        .await?;
+
+        let device = match options.both_states {
+            Some((keypress, encoder)) => device
+                .with_supports_both_keypress_states(keypress)
+                .with_supports_both_encoder_states(encoder),
+            None => device,
+        }
+        .with_extra_init(options.extra_init);
+
+        if let Some(max) = options.wait_ready {
+            device.wait_until_ready(max).await?;
+        }
+
+        Ok(device)
+    }
+
+    /// Attempts to connect to the device
+    ///
+    /// Doesn't check provided configuration against the known model of the device,
+    /// and allows connecting to the device already connected by this process
+    ///
+    /// **Deprecated:** use [Device::connect_with], this form is kept for compatibility
+    /// and will be removed in a future release
+    #[deprecated(note = "use Device::connect_with")]
+    pub async fn connect(
+        dev: &HidDeviceInfo,
+        protocol_version: impl TryInto<ProtocolVersion>,
+        key_count: usize,
+        encoder_count: usize,
+    ) -> Result<Device, MirajazzError> {
+        let options = ConnectOptions::new()
+            .protocol_version(legacy_protocol_version(protocol_version))
+            .keys(key_count)
+            .encoders(encoder_count)
+            .allow_mismatch(true)
+            .shared(true);
+
+        Device::connect_with(dev, options).await
+    }
+
+    /// Attempts to connect to the device, even if provided configuration doesn't match
+    /// the known model of the device
+    ///
+    /// **Deprecated:** use [Device::connect_with] with [ConnectOptions::allow_mismatch]
+    #[deprecated(note = "use Device::connect_with")]
+    pub async fn connect_allowing_mismatch(
+        dev: &HidDeviceInfo,
+        protocol_version: impl TryInto<ProtocolVersion>,
+        key_count: usize,
+        encoder_count: usize,
+    ) -> Result<Device, MirajazzError> {
+        let options = ConnectOptions::new()
+            .protocol_version(legacy_protocol_version(protocol_version))
+            .keys(key_count)
+            .encoders(encoder_count)
+            .allow_mismatch(true);
+
+        Device::connect_with(dev, options).await
+    }
+
+    /// Attempts to connect to the device, even if it's already connected by this process
+    ///
+    /// **NOTE:** Multiple handles will compete for the input reports
+    ///
+    /// **Deprecated:** use [Device::connect_with] with [ConnectOptions::shared]
+    #[deprecated(note = "use Device::connect_with")]
+    pub async fn connect_shared(
+        dev: &HidDeviceInfo,
+        protocol_version: impl TryInto<ProtocolVersion>,
+        key_count: usize,
+        encoder_count: usize,
+    ) -> Result<Device, MirajazzError> {
+        let options = ConnectOptions::new()
+            .protocol_version(legacy_protocol_version(protocol_version))
+            .keys(key_count)
+            .encoders(encoder_count)
+            .shared(true);
+
+        Device::connect_with(dev, options).await
+    }
+
+    async fn connect_device(
+        device: HidDevice,
+        protocol_version: ProtocolVersion,
+        key_count: usize,
+        encoder_count: usize,
+        checks: ConnectChecks,
+    ) -> Result<Device, MirajazzError> {
+        assert!(
+            protocol_version != ProtocolVersion::V0,
+            "Use protocol_version 1 instead of 0. Protocol version 0 will be set automatically when needed"
+        );
 
         let serial_number = match (device.serial_number.clone(), protocol_version) {
             // There is pv 1 devices that don't have serial number *at all*
@@ -236,42 +1332,140 @@ impl Device {
             // Because 355499441494 is a hardcoded serial for pv 1 devices,
             // and Windows also fucks up the serial number for these devices,
             // just hardcode it on our side ¯\_(ツ)_/¯
-            (_, 1) => "355499441494".to_string(),
+            (_, ProtocolVersion::V1) => Some("355499441494".to_string()),
 
-            // Everything with pv 2 and greater should have serial
-            (Some(serial), _) => serial,
+            // Everything with pv 2 and greater should have serial, empty ones are treated as missing
+            (Some(serial), _) => {
+                Some(normalize_serial(&serial)).filter(|serial| !serial.is_empty())
+            }
 
-            // If there is some pv 2+ device without serial number, return an error
-            (None, _) => return Err(MirajazzError::InvalidDeviceError),
+            // Some platform backends don't report serials at all, such devices are told apart by path
+            (None, _) => None,
         };
 
-        let (reader, writer) = device.open().await?;
+        let id = DeviceId::from_info(&device, protocol_version);
+
+        let firmware_report = Device::read_firmware_report(&device).await?;
+        let firmware_version = firmware_report
+            .as_ref()
+            .map(|report| String::from_utf8_lossy(report).to_string());
+        let model = firmware_report.as_deref().and_then(parse_model);
+
+        let kind = Kind::from_vid_pid_model(device.vendor_id, device.product_id, model.as_deref());
+
+        if checks.match_kind {
+            if let Some(kind) = kind {
+                check_kind(&id, kind, protocol_version, key_count, encoder_count)?;
+            }
+        }
+
+        // Registration is dropped on any early return below, so the entry doesn't linger
+        let registration = if checks.exclusive {
+            Some(OpenDeviceRegistration::register(&device.id, &id)?)
+        } else {
+            None
+        };
+
+        let (reader, writer) = match device.open().await {
+            Ok(handles) => handles,
+            Err(err) if is_busy_error(&err) => {
+                return Err(MirajazzError::DeviceBusy {
+                    vid: device.vendor_id,
+                    pid: device.product_id,
+                    serial: serial_number,
+                })
+            }
+            Err(err) => return Err(err.into()),
+        };
 
         // If device is missing serial number, it's probably firmware `1.0.0.0`
         // In this case, set protocol version to 0
         //
         // This protocol version can only be set automatically
-        let override_protocol_version = if device.serial_number.is_none() {
-            0
-        } else {
-            protocol_version // Otherwise, keep provided protocol version
-        };
+        let override_protocol_version =
+            if device.serial_number.is_none() && protocol_version == ProtocolVersion::V1 {
+                ProtocolVersion::V0
+            } else {
+                protocol_version // Otherwise, keep provided protocol version
+            };
 
         Ok(Device {
+            id,
+            hid_id: device.id.clone(),
             vid: device.vendor_id,
             pid: device.product_id,
             serial_number,
+            product: descriptor_string(&device.name),
+            manufacturer: device.manufacturer.as_deref().and_then(descriptor_string),
+            parsed_firmware_version: Mutex::new(
+                firmware_report
+                    .as_deref()
+                    .and_then(|report| parse_firmware_version(report).ok().flatten()),
+            ),
             firmware_version,
+            model,
             protocol_version: override_protocol_version,
-            supports_both_keypress_states: override_protocol_version > 2,
-            supports_both_encoder_states: override_protocol_version > 2,
+            protocol: Arc::new(MiraboxProtocol),
+            extra_init: Vec::new(),
+            supports_both_keypress_states: override_protocol_version.supports_both_states(),
+            supports_both_encoder_states: override_protocol_version.supports_both_states(),
+            image_readback_command: None,
+            version_query_command: None,
+            standby_image_command: None,
             key_count,
             encoder_count,
-            reader: Arc::new(Mutex::new(reader)),
-            writer: Arc::new(Mutex::new(writer)),
-            packet_size: if protocol_version >= 2 { 1024 } else { 512 },
+            zones: vec![],
+            native_image_size: kind.and_then(|kind| kind.image_size),
+            geometry: kind.and_then(|kind| kind.geometry.clone()),
+            capabilities: match kind {
+                Some(kind) => kind.capabilities,
+                None => Capabilities {
+                    encoders: encoder_count > 0,
+                    ..Capabilities::ALL
+                },
+            },
+            orientation: std::sync::Mutex::new(Orientation::Normal),
+            logical_keys: Arc::new(std::sync::RwLock::new(vec![])),
+            auto_adapt_images: false,
+            reader: Arc::new(Mutex::new(InputTransport::Hid(reader))),
+            writer: Arc::new(Mutex::new(OutputTransport::Hid(writer))),
+            transfer_lock: Arc::new(TransferLock::default()),
+            input_mode: InputMode::Push,
+            read_policy: ReadPolicy::Lenient,
+            encoder_mode: EncoderMode::Normal,
+            packet_size: Arc::new(AtomicUsize::new(protocol_version.packet_size())),
+            negotiate_packet_size: false,
+            input_report_size: protocol_version.input_report_size(),
             image_cache: Mutex::new(HashMap::new()),
+            remembered_images: Mutex::new(HashMap::new()),
+            last_flush_report: Mutex::new(FlushReport::default()),
+            brightness: Mutex::new(None),
+            brightness_throttle: Throttle::new(BRIGHTNESS_THROTTLE),
+            clears_on_sleep: false,
+            clear_strategy: ClearStrategy::Command,
+            display_off: false.into(),
+            display_off_flush: DisplayOffFlush::Queue,
+            display_on_commit: false,
+            boot_delay: None,
+            handshaken: false.into(),
             initialized: false.into(),
+            init_lock: Mutex::new(()),
+            stats: SharedStats::default(),
+            commit_ack_timeout: None,
+            ack_timeout: None,
+            queued_reports: Arc::new(Mutex::new(VecDeque::new())),
+            acks: Arc::new(AckMailbox::default()),
+            pending_read: Arc::new(PendingRead::default()),
+            resend_on_render_error: false,
+            image_checksum: None,
+            retry_policy: RetryPolicy::default(),
+            key_codes: HashMap::new(),
+            conversions: Mutex::new(HashMap::new()),
+            capability_events: false,
+            virtual_keys: Vec::new(),
+            aux: Mutex::new(None),
+            aux_queries: false,
+            _registration: registration,
         })
     }
 
@@ -298,313 +1492,2151 @@ impl Device {
         self
     }
 
-    #[cfg(not(target_os = "windows"))]
+    /// Overrides size of input reports requested from the device
+    ///
+    /// Defaults to 512 bytes for protocol versions 0 and 1, and 1024 bytes for everything newer
+    pub fn with_input_report_size(mut self, size: usize) -> Self {
+        self.input_report_size = size;
+        self
+    }
+
+    /// Marks device as forgetting displayed images while sleeping,
+    /// so they are sent again by [Device::wake]
+    pub fn with_clears_on_sleep(mut self, clears: bool) -> Self {
+        self.clears_on_sleep = clears;
+        self
+    }
+
+    /// Sets how [Device::clear_button_image] and [Device::clear_all_button_images] clear buttons
+    pub fn with_clear_strategy(mut self, clear_strategy: ClearStrategy) -> Self {
+        self.clear_strategy = clear_strategy;
+        self
+    }
+
+    /// Sets what flushes do while the display is turned off with [Device::display_off]
+    pub fn with_display_off_flush(mut self, display_off_flush: DisplayOffFlush) -> Self {
+        self.display_off_flush = display_off_flush;
+        self
+    }
+
+    /// Marks device as needing a commit after [Device::display_on] before it shows anything
+    pub fn with_display_on_commit(mut self, commit: bool) -> Self {
+        self.display_on_commit = commit;
+        self
+    }
+
+    /// Sets how long the device needs to finish booting after being connected,
+    /// used by [Device::show_splash]
+    ///
+    /// Some firmwares drop the first transfer while still showing their boot logo
+    pub fn with_boot_delay(mut self, delay: Option<Duration>) -> Self {
+        self.boot_delay = delay;
+        self
+    }
+
+    /// Makes [Device::flush] wait for the commit acknowledgment, so the next flush doesn't get lost
+    ///
+    /// If acknowledgment doesn't arrive in time, flush returns anyway and the timeout is counted in stats.
+    /// Input reports received while waiting are passed to the reader
+    pub fn with_commit_ack_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.commit_ack_timeout = timeout;
+        self
+    }
+
+    /// Makes image transfers and commits wait for acknowledgment, for noticing images the device rejected
+    ///
+    /// Rejection fails with [MirajazzError::Nack] carrying the reply, no acknowledgment within the
+    /// timeout fails with [MirajazzError::AckTimeout]. Input reports received while waiting are passed
+    /// to the reader. Takes precedence over [Device::with_commit_ack_timeout] when both are set
+    pub fn with_ack_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    /// Sets minimal interval between brightness commands sent by [Device::set_brightness_throttled]
+    pub fn with_brightness_throttle(mut self, interval: Duration) -> Self {
+        self.brightness_throttle = Throttle::new(interval);
+        self
+    }
+
+    /// Makes [Device::query_firmware_version] use the interface opened with
+    /// [Device::open_aux_interface] when it's open
+    pub fn with_aux_queries(mut self, aux_queries: bool) -> Self {
+        self.aux_queries = aux_queries;
+        self
+    }
+
+    /// Makes [Device::flush] send the affected key again when device reports a render error,
+    /// instead of returning [MirajazzError::DeviceReportedError]
+    ///
+    /// Render errors are only noticed during flush if device was configured with
+    /// [Device::with_commit_ack_timeout], otherwise they arrive as notifications to the reader
+    pub fn with_resend_on_render_error(mut self, resend: bool) -> Self {
+        self.resend_on_render_error = resend;
+        self
+    }
+
+    /// Sets checksum of image data sent in the transfer header, [None] leaves the header zeroed
+    ///
+    /// Transfers rejected by the device are sent again a couple of times, then the key is
+    /// left pending and flush returns [MirajazzError::ChecksumMismatch]
+    pub fn with_image_checksum(mut self, image_checksum: Option<ChecksumKind>) -> Self {
+        self.image_checksum = image_checksum;
+        self
+    }
+
+    /// Sets how operations failing with transient errors are retried, like the splash
+    /// shown right after boot, see [crate::retry::with_policy]
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Returns how operations failing with transient errors are retried
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    /// Makes the handshake check that device acknowledges packets of the configured size,
+    /// and switch to the other size of the protocol family if it doesn't
+    ///
+    /// For devices that changed packet size after a firmware update. Detected size is recorded
+    /// in [Device::stats]. Requires firmware that acknowledges commits
+    pub fn with_packet_size_negotiation(mut self, negotiate: bool) -> Self {
+        self.negotiate_packet_size = negotiate;
+        self
+    }
+
+    /// Returns size of output packets, without report id
+    pub fn packet_size(&self) -> usize {
+        self.packet_size.load(Ordering::Relaxed)
+    }
+
+    /// Makes readers send [crate::state::DeviceStateUpdate::CapabilityObserved] the first time
+    /// the device reports buttons, encoders or touch, for showing controls that actually work
+    pub fn with_capability_events(mut self, capability_events: bool) -> Self {
+        self.capability_events = capability_events;
+        self
+    }
+
+    /// Sets how readers interpret encoder inputs
+    pub fn with_encoder_mode(mut self, encoder_mode: EncoderMode) -> Self {
+        self.encoder_mode = encoder_mode;
+        self
+    }
+
+    /// Sets how the device delivers input reports
+    ///
+    /// For devices that never send input reports by themselves, use [InputMode::Polled]
+    pub fn with_input_mode(mut self, input_mode: InputMode) -> Self {
+        self.input_mode = input_mode;
+        self
+    }
+
+    /// Sets what readers do with malformed input reports
+    pub fn with_read_policy(mut self, read_policy: ReadPolicy) -> Self {
+        self.read_policy = read_policy;
+        self
+    }
+
+    /// Sets groups of keys that use different image formats
+    ///
+    /// When set, [Device::set_button_image] rejects images routed to the wrong zone
+    pub fn with_zones(mut self, zones: Vec<Zone>) -> Self {
+        self.zones = zones;
+        self
+    }
+
+    /// Enables [Device::firmware_version] to fall back to querying the version with an output report
+    /// using provided opcode, for backends that can't read feature reports, like Windows
+    ///
+    /// Reply is the input report echoing the opcode after the command prefix, followed by the version
+    pub fn with_version_query_command(mut self, command: Option<[u8; 3]>) -> Self {
+        self.version_query_command = command;
+        self
+    }
+
+    /// Enables reading back button images using provided command opcode
+    ///
+    /// Only some firmwares support this, and the opcode differs between them
+    pub fn with_image_readback_command(mut self, command: Option<[u8; 3]>) -> Self {
+        self.image_readback_command = command;
+        self
+    }
+
+    /// Overrides native resolution of key images, after rotation, used for validating image formats
+    ///
+    /// Set automatically for known models
+    pub fn with_native_image_size(mut self, size: Option<(usize, usize)>) -> Self {
+        self.native_image_size = size;
+        self
+    }
+
+    /// Overrides physical arrangement of keys and encoders, set automatically for known models
+    ///
+    /// Returns [MirajazzError::InvalidGeometry] if geometry doesn't match key and encoder count
+    pub fn with_geometry(mut self, geometry: Option<Geometry>) -> Result<Self, MirajazzError> {
+        if let Some(geometry) = &geometry {
+            geometry.validate(self.key_count, self.encoder_count)?;
+        }
+
+        self.geometry = geometry;
+        Ok(self)
+    }
+
+    /// Translates raw key codes reported by the device to key indices, for firmwares that report
+    /// scan codes with gaps. Key index is passed to the parser instead of the raw code
+    ///
+    /// Codes missing from the table are treated as malformed reports, see [Device::with_read_policy].
+    /// Returns [MirajazzError::DuplicateKeyCode] if several codes map to the same key
+    pub fn with_key_codes(mut self, key_codes: HashMap<u8, u8>) -> Result<Self, MirajazzError> {
+        let mut seen = HashSet::new();
+
+        for key in key_codes.values() {
+            if !seen.insert(*key) {
+                return Err(MirajazzError::DuplicateKeyCode { key: *key });
+            }
+        }
+
+        self.key_codes = key_codes;
+        Ok(self)
+    }
+
+    /// Makes readers report long presses, encoder presses or touch regions as keys beyond the
+    /// physical ones, so they can be bound like any other key
+    ///
+    /// Inputs triggering virtual keys are reported as [crate::state::DeviceStateUpdate::VirtualKeyDown]
+    /// and [crate::state::DeviceStateUpdate::VirtualKeyUp] instead, except long presses, which are
+    /// reported after the release of the key. Returns [MirajazzError::InvalidKeyIndex] if index of
+    /// a virtual key overlaps the physical keys
+    pub fn with_virtual_keys(
+        mut self,
+        virtual_keys: Vec<VirtualKey>,
+    ) -> Result<Self, MirajazzError> {
+        if virtual_keys
+            .iter()
+            .any(|virtual_key| (virtual_key.index as usize) < self.key_count)
+        {
+            return Err(MirajazzError::InvalidKeyIndex);
+        }
+
+        self.virtual_keys = virtual_keys;
+        Ok(self)
+    }
+
+    /// Sets packets sent after the built-in initialization ones, for firmwares that need an extra
+    /// handshake before accepting images
+    ///
+    /// Packets are payloads as for [Device::write_extended_data], padded to the packet size
+    pub fn with_extra_init(mut self, packets: Vec<Vec<u8>>) -> Self {
+        self.extra_init = packets;
+        self
+    }
+
+    /// Sets how commands are encoded, for firmware variants with slightly different command sets
+    pub fn with_protocol(mut self, protocol: impl DeviceProtocol + 'static) -> Self {
+        self.protocol = Arc::new(protocol);
+        self
+    }
+
+    /// Overrides features the firmware supports, for devices that aren't known or differ from their model
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Rescales images whose format doesn't match native resolution or the zone of the key,
+    /// instead of returning an error
+    pub fn with_auto_adapt_images(mut self, adapt: bool) -> Self {
+        self.auto_adapt_images = adapt;
+        self
+    }
+
+    /// Enables uploading the standby image with [Device::set_standby_image], using provided command
+    pub fn with_standby_image_command(mut self, command: Option<UploadCommand>) -> Self {
+        self.standby_image_command = command;
+        self
+    }
+
     pub async fn read_firmware_version_from_raw_device(
         dev: &HidDevice,
     ) -> Result<Option<String>, MirajazzError> {
+        let report = Device::read_firmware_report(dev).await?;
+
+        Ok(report.map(|report| String::from_utf8_lossy(&report).to_string()))
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    pub(crate) async fn read_firmware_report(
+        dev: &HidDevice,
+    ) -> Result<Option<Vec<u8>>, MirajazzError> {
         let mut fw_buffer = [0u8; 20];
         fw_buffer[0] = 0x01;
 
         let firmware_version_size = dev.read_feature_report(&mut fw_buffer).await?;
 
-        Ok(Some(
-            String::from_utf8_lossy(&fw_buffer[..firmware_version_size]).to_string(),
-        ))
+        Ok(Some(fw_buffer[..firmware_version_size].to_vec()))
     }
 
     // TRACK: https://github.com/4ndv/mirajazz/issues/10
     #[cfg(target_os = "windows")]
-    pub async fn read_firmware_version_from_raw_device(
+    pub(crate) async fn read_firmware_report(
         dev: &HidDevice,
-    ) -> Result<Option<String>, MirajazzError> {
+    ) -> Result<Option<Vec<u8>>, MirajazzError> {
         Ok(None)
     }
 }
 
 /// Instance methods of the struct
 impl Device {
+    /// Returns identity of the device
+    pub fn id(&self) -> &DeviceId {
+        &self.id
+    }
+
+    /// Returns platform identifier of the device
+    pub fn hid_id(&self) -> &HidDeviceId {
+        &self.hid_id
+    }
+
+    /// Returns model reported by the firmware alongside its version, helps telling apart clones sharing a PID
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// Returns physical arrangement of keys and encoders, if known
+    pub fn geometry(&self) -> Option<&Geometry> {
+        self.geometry.as_ref()
+    }
+
+    /// Returns features the firmware supports
+    ///
+    /// Taken from the known model, devices that aren't known are assumed to support everything
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
     /// Returns key count
     pub fn key_count(&self) -> usize {
         self.key_count
     }
 
-    /// Returns encoder count
-    pub fn encoder_count(&self) -> usize {
-        self.encoder_count
+    /// Returns encoder count
+    pub fn encoder_count(&self) -> usize {
+        self.encoder_count
+    }
+
+    /// Returns statistics of device operations
+    pub fn stats(&self) -> DeviceStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns protocol version of the device
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Returns groups of keys with different image formats
+    pub fn zones(&self) -> &[Zone] {
+        &self.zones
+    }
+
+    /// Returns zone the key belongs to
+    pub fn zone_for_key(&self, key: u8) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.contains(key))
+    }
+
+    /// Returns how the device delivers input reports
+    pub fn input_mode(&self) -> &InputMode {
+        &self.input_mode
+    }
+
+    /// Returns size of input reports requested from the device
+    pub fn input_report_size(&self) -> usize {
+        self.input_report_size
+    }
+
+    /// Returns serial number of the device, [None] if platform backend didn't report it
+    ///
+    /// Use [Device::id] for identifying devices, it falls back to platform path
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    /// Returns product string of the USB descriptor, like "Ajazz AKP153", if present
+    pub fn product_string(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    /// Returns manufacturer string of the USB descriptor, if present
+    pub fn manufacturer_string(&self) -> Option<&str> {
+        self.manufacturer.as_deref()
+    }
+
+    pub fn supports_both_encoder_states(&self) -> bool {
+        self.supports_both_encoder_states
+    }
+
+    /// Waits until device finishes booting and starts answering, for using right after connecting
+    ///
+    /// Repeatedly queries firmware version, backing off between attempts, until device answers.
+    /// On platforms where the query isn't supported, version is queried with the command set by
+    /// [Device::with_version_query_command], or keep-alive packet is written and any input report
+    /// counts as an answer. Doesn't initialize the device. Returns how long it took, or [MirajazzError::NotReady]
+    /// if device didn't answer within `max`
+    pub async fn wait_until_ready(&self, max: Duration) -> Result<Duration, MirajazzError> {
+        let started = Instant::now();
+        let mut backoff = Duration::from_millis(10);
+
+        loop {
+            if self.is_ready().await {
+                return Ok(started.elapsed());
+            }
+
+            let remaining = match max.checked_sub(started.elapsed()) {
+                Some(remaining) if !remaining.is_zero() => remaining,
+                _ => return Err(MirajazzError::NotReady),
+            };
+
+            time::sleep(backoff.min(remaining)).await;
+            backoff = (backoff * 2).min(Duration::from_millis(200));
+        }
+    }
+
+    /// Checks if device answers to a harmless query
+    async fn is_ready(&self) -> bool {
+        let device = match HidBackend::default().query_devices(&self.hid_id).await {
+            Ok(mut devices) => devices.next(),
+            Err(_) => return false,
+        };
+
+        let device = match device {
+            Some(device) => device,
+            None => return false,
+        };
+
+        match Device::read_firmware_report(&device).await {
+            Ok(Some(report)) => !report.is_empty(),
+            Ok(None) => self.answers_query().await,
+            Err(_) => false,
+        }
+    }
+
+    /// Checks if device answers with an input report, for platforms where feature reports
+    /// can't be read. Input reports received are passed to the reader
+    async fn answers_query(&self) -> bool {
+        if self.version_query_command.is_some() {
+            return matches!(self.query_version_by_command().await, Ok(Some(_)));
+        }
+
+        if self.write_command(Command::KeepAlive).await.is_err() {
+            return false;
+        }
+
+        match self.read_report(NEGOTIATION_TIMEOUT).await {
+            Ok(Some(report)) => {
+                if !is_noop_report(&report) {
+                    self.queued_reports.lock().await.push_back(report);
+                }
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Opens secondary interface of the device with provided usage id, on the same usage page
+    ///
+    /// Some devices expose several interfaces, with vendor tools using the second one for
+    /// firmware and status operations while images go to the first. Opening it again replaces
+    /// previously opened one, it's closed together with the device
+    pub async fn open_aux_interface(&self, usage_id: u16) -> Result<(), MirajazzError> {
+        let backend = HidBackend::default();
+
+        let primary = match backend.query_devices(&self.hid_id).await?.last() {
+            Some(primary) => primary,
+            None => return Err(MirajazzError::DeviceNotFoundError),
+        };
+
+        let sibling = backend
+            .enumerate()
+            .await?
+            .find(|device| {
+                device.id != primary.id
+                    && device.vendor_id == primary.vendor_id
+                    && device.product_id == primary.product_id
+                    && device.serial_number == primary.serial_number
+                    && device.usage_page == primary.usage_page
+                    && device.usage_id == usage_id
+            })
+            .await;
+
+        let device = match sibling {
+            Some(device) => device,
+            None => return Err(MirajazzError::DeviceNotFoundError),
+        };
+
+        let (reader, writer) = device.open().await?;
+
+        *self.aux.lock().await = Some(Arc::new(AuxInterface {
+            device,
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        }));
+
+        Ok(())
+    }
+
+    /// Closes secondary interface opened with [Device::open_aux_interface]
+    pub async fn close_aux_interface(&self) {
+        self.aux.lock().await.take();
+    }
+
+    /// Checks if secondary interface is open
+    pub async fn has_aux_interface(&self) -> bool {
+        self.aux.lock().await.is_some()
+    }
+
+    /// Returns secondary interface, [MirajazzError::NoAuxInterface] if it isn't open
+    async fn aux_interface(&self) -> Result<Arc<AuxInterface>, MirajazzError> {
+        match self.aux.lock().await.as_ref() {
+            Some(aux) => Ok(aux.clone()),
+            None => Err(MirajazzError::NoAuxInterface),
+        }
+    }
+
+    /// Writes command to the secondary interface, padded the same way as on the primary one
+    pub async fn aux_send_command(&self, command: Command<'_>) -> Result<(), MirajazzError> {
+        let aux = self.aux_interface().await?;
+
+        aux.writer
+            .lock()
+            .await
+            .write_output_report(&self.extend_payload(&self.protocol.encode(&command))?)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Reads single input report from the secondary interface, returns [None] if timeout was reached
+    pub async fn aux_read_report(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, MirajazzError> {
+        let aux = self.aux_interface().await?;
+
+        read_sized_report(&aux.reader, self.input_report_size, timeout).await
+    }
+
+    /// Queries firmware version of the connected device
+    ///
+    /// Uses secondary interface if it's open and device was configured with [Device::with_aux_queries]
+    pub async fn query_firmware_version(&self) -> Result<Option<String>, MirajazzError> {
+        let report = self.query_firmware_report().await?;
+
+        Ok(report.map(|report| String::from_utf8_lossy(&report).to_string()))
+    }
+
+    /// Returns firmware version string, querying the device only if it wasn't read yet
+    ///
+    /// Version read while connecting is reused, so repeated calls don't send anything.
+    /// If feature report can't be read or is empty, falls back to the output report query
+    /// configured with [Device::with_version_query_command].
+    ///
+    /// Returns [None] if device doesn't answer in time or reports an empty version,
+    /// [MirajazzError::BadData] if the answer isn't a version string
+    pub async fn firmware_version(&self) -> Result<Option<String>, MirajazzError> {
+        let mut version = self.parsed_firmware_version.lock().await;
+
+        if version.is_some() {
+            return Ok(version.clone());
+        }
+
+        let report = match time::timeout(RESPONSE_TIMEOUT, self.query_firmware_report()).await {
+            Ok(Ok(report)) => report,
+            // Some backends fail reading zero-length feature reports instead of returning nothing
+            Ok(Err(MirajazzError::HidError(err))) => {
+                warn!("Failed to read firmware version feature report: {}", err);
+
+                None
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(_) => None,
+        };
+
+        *version = match report {
+            Some(report) => parse_firmware_version(&report)?,
+            None => None,
+        };
+
+        if version.is_none() {
+            *version = self.query_version_by_command().await?;
+        }
+
+        Ok(version.clone())
+    }
+
+    /// Queries firmware version with an output report, see [Device::with_version_query_command]
+    ///
+    /// Input reports received while waiting are passed to the reader
+    async fn query_version_by_command(&self) -> Result<Option<String>, MirajazzError> {
+        let opcode = match self.version_query_command {
+            Some(opcode) => opcode,
+            None => return Ok(None),
+        };
+
+        self.write_command(Command::QueryVersion { opcode }).await?;
+
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let report = match self.read_report(remaining).await? {
+                Some(report) => report,
+                None => break,
+            };
+
+            if is_version_reply(&report, opcode) {
+                return parse_version_reply(&report);
+            }
+
+            self.queued_reports.lock().await.push_back(report);
+        }
+
+        Ok(None)
+    }
+
+    /// Returns everything known about the device in one struct, querying firmware version if needed
+    ///
+    /// Fields the device can't report are [None], failing version query is logged and doesn't fail the call
+    pub async fn query_info(&self) -> Result<MirajazzDeviceInfo, MirajazzError> {
+        let firmware_version = match self.firmware_version().await {
+            Ok(version) => version,
+            Err(err) => {
+                warn!("Failed to query firmware version of {}: {}", self.id, err);
+
+                None
+            }
+        };
+
+        Ok(MirajazzDeviceInfo {
+            vendor_id: self.vid,
+            product_id: self.pid,
+            serial_number: self.serial_number.clone(),
+            product: self.product.clone(),
+            manufacturer: self.manufacturer.clone(),
+            firmware_version,
+            model: self.model.clone(),
+            key_count: self.key_count,
+            encoder_count: self.encoder_count,
+            packet_size: self.packet_size(),
+            protocol_version: self.protocol_version,
+        })
+    }
+
+    /// Reads firmware version report, from the secondary interface if device was configured
+    /// with [Device::with_aux_queries]
+    async fn query_firmware_report(&self) -> Result<Option<Vec<u8>>, MirajazzError> {
+        let aux = self.aux.lock().await.clone();
+
+        if let Some(aux) = aux.filter(|_| self.aux_queries) {
+            return Device::read_firmware_report(&aux.device).await;
+        }
+
+        let device = match HidBackend::default()
+            .query_devices(&self.hid_id)
+            .await?
+            .last()
+        {
+            Some(device) => device,
+            None => return Err(MirajazzError::DeviceNotFoundError),
+        };
+
+        Device::read_firmware_report(&device).await
+    }
+
+    /// Performs the handshake, without touching the display
+    async fn handshake(&self) -> Result<(), MirajazzError> {
+        if self.handshaken.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        let _init = self.init_lock.lock().await;
+
+        // Another task could have finished the handshake while we were waiting
+        if self.handshaken.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        self.write_command(Command::Wake).await?;
+
+        if self.negotiate_packet_size {
+            self.negotiate_packet_size().await?;
+        }
+
+        // Marked only after the packets were sent, so nothing else is written before them
+        self.handshaken.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Finds packet size the device acknowledges, trying the configured one first
+    ///
+    /// Keeps the configured size if device doesn't acknowledge commits at any size
+    async fn negotiate_packet_size(&self) -> Result<(), MirajazzError> {
+        let configured = self.packet_size();
+        let alternate = if configured == 512 { 1024 } else { 512 };
+
+        for size in [configured, alternate] {
+            self.packet_size.store(size, Ordering::Relaxed);
+
+            if self.commit_acknowledged().await? {
+                if size != configured {
+                    warn!(
+                        "Device ignored {} byte packets, using {} byte packets instead",
+                        configured, size
+                    );
+                }
+
+                self.stats
+                    .update(|stats| stats.negotiated_packet_size = Some(size));
+
+                return Ok(());
+            }
+        }
+
+        self.packet_size.store(configured, Ordering::Relaxed);
+
+        warn!("Device didn't acknowledge packets of any size, keeping the configured one");
+
+        Ok(())
+    }
+
+    /// Sends a commit with nothing pending and checks if device acknowledges it
+    async fn commit_acknowledged(&self) -> Result<bool, MirajazzError> {
+        let _expected = self.acks.expect();
+        self.write_command(Command::Commit).await?;
+
+        let deadline = Instant::now() + NEGOTIATION_TIMEOUT;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let report = match self.read_reply(remaining).await? {
+                Some(report) => report,
+                None => break,
+            };
+
+            if is_commit_ack(&report) {
+                return Ok(true);
+            }
+
+            self.queued_reports.lock().await.push_back(report);
+        }
+
+        Ok(false)
+    }
+
+    /// Initializes the device display, performing the handshake first
+    ///
+    /// Display initialization blanks the screen, so it's only done before display commands
+    ///
+    /// Handshake and initialization packets are sent back to back, before any other write
+    /// waiting for them, even if several tasks start using a fresh device at once.
+    /// Packets set with [Device::with_extra_init] are sent right after the built-in ones
+    ///
+    /// Done automatically, can be called to initialize early. If it fails, the next call starts over
+    pub async fn initialize(&self) -> Result<(), MirajazzError> {
+        if self.initialized.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        // Negotiation has to read responses in between, so it goes through the usual handshake
+        if self.negotiate_packet_size {
+            self.handshake().await?;
+        }
+
+        let _init = self.init_lock.lock().await;
+
+        if self.initialized.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        let handshaken = self.handshaken.load(Ordering::Acquire);
+
+        {
+            let _transfer = self.transfer_lock.lock(&self.stats).await;
+
+            if !handshaken {
+                self.write_packet(&self.extend_payload(&self.protocol.encode(&Command::Wake))?)
+                    .await?;
+            }
+
+            self.write_packet(
+                &self.extend_payload(&self.protocol.encode(&Command::Brightness(0)))?,
+            )
+            .await?;
+
+            for packet in &self.extra_init {
+                self.write_packet(&self.extend_payload(packet)?).await?;
+            }
+        }
+
+        self.handshaken.store(true, Ordering::Release);
+        self.initialized.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Returns true if the display was initialized, see [Device::initialize]
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.load(Ordering::Acquire)
+    }
+
+    /// Resets the device
+    pub async fn reset(&self) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        self.set_brightness(100).await?;
+        self.clear_all_button_images().await?;
+
+        Ok(())
+    }
+
+    /// Sets brightness of the device, value range is 0 - 100, values above are clamped to 100
+    ///
+    /// Brightness setters return [MirajazzError::UnsupportedOperation] if device can't set brightness,
+    /// see [Device::capabilities]
+    pub async fn set_brightness(&self, percent: u8) -> Result<(), MirajazzError> {
+        self.apply_brightness(Brightness::clamped(percent)).await
+    }
+
+    /// Sets brightness of the device, value range is 0 - 100
+    ///
+    /// Returns [MirajazzError::InvalidBrightness] for values out of range instead of clamping them
+    pub async fn set_brightness_strict(&self, percent: u8) -> Result<(), MirajazzError> {
+        self.apply_brightness(Brightness::new(percent)?).await
+    }
+
+    /// Sets brightness of the device, coalescing rapid calls, for analog sources like faders
+    ///
+    /// At most one brightness command is sent per interval set by [Device::with_brightness_throttle].
+    /// The call returns once its value is sent, returning error of sending, or right away once a newer
+    /// call supersedes it, so brightness always settles on the latest value. If the sending call
+    /// is cancelled, the newest waiting call sends instead. [Device::shutdown] discards waiting calls
+    pub async fn set_brightness_throttled(&self, percent: u8) -> Result<(), MirajazzError> {
+        self.capabilities.require_brightness()?;
+
+        self.brightness_throttle
+            .send(self.apply_brightness(Brightness::clamped(percent)))
+            .await
+    }
+
+    /// Returns last brightness set on the device, [None] if it wasn't set yet
+    pub async fn brightness(&self) -> Option<Brightness> {
+        *self.brightness.lock().await
+    }
+
+    async fn apply_brightness(&self, brightness: Brightness) -> Result<(), MirajazzError> {
+        self.capabilities.require_brightness()?;
+
+        self.initialize().await?;
+
+        self.write_command(Command::Brightness(brightness.percent()))
+            .await?;
+
+        *self.brightness.lock().await = Some(brightness);
+
+        Ok(())
+    }
+
+    /// Sets brightness of the knob LEDs, value range is 0 - 100
+    pub async fn set_led_brightness(&self, percent: u8) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        let percent = percent.clamp(0, 100);
+
+        self.write_command(Command::LedBrightness(percent)).await?;
+
+        Ok(())
+    }
+
+    /// Sets the color of each knob LED individually.
+    /// `colors` must contain exactly one `[r, g, b]` entry per LED.
+    pub async fn set_led_colors(&self, colors: &[[u8; 3]]) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        self.write_command(Command::LedColors(colors)).await?;
+
+        Ok(())
+    }
+
+    /// Writes raw image data to the device, not to be used directly
+    async fn send_image(&self, key: u8, image_data: &[u8]) -> Result<(), MirajazzError> {
+        self.send_image_tracked(key, image_data, &mut 0).await
+    }
+
+    /// Writes raw image data to the device, counting payload bytes that were sent
+    async fn send_image_tracked(
+        &self,
+        key: u8,
+        image_data: &[u8],
+        bytes_sent: &mut usize,
+    ) -> Result<(), MirajazzError> {
+        let started = Instant::now();
+
+        let _transfer = self.transfer_lock.lock(&self.stats).await;
+
+        let checksum = self
+            .image_checksum
+            .map_or(0, |kind| kind.compute(image_data));
+
+        let header = &self.protocol.encode(&Command::ImageHeader {
+            key,
+            length: image_data.len(),
+            checksum,
+        });
+
+        // Rejected images are sent again right away, other errors are returned as is
+        let policy = RetryPolicy {
+            max_attempts: 1 + CHECKSUM_RETRIES,
+            base_delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            deadline: None,
+            is_retryable: |error| matches!(error, MirajazzError::ChecksumMismatch { .. }),
+            ..RetryPolicy::default()
+        };
+
+        // Bytes sent by the last attempt, every attempt starts from scratch
+        let progress = &AtomicUsize::new(0);
+
+        let result = retry::with_policy(&policy, || async move {
+            let mut sent = 0;
+
+            let result = self
+                .send_image_attempt(key, header, image_data, &mut sent)
+                .await;
+
+            progress.store(sent, Ordering::Relaxed);
+
+            result
+        })
+        .await;
+
+        *bytes_sent = progress.load(Ordering::Relaxed);
+
+        result?;
+
+        self.stats
+            .update(|stats| stats.key_transfer.record(started.elapsed()));
+
+        Ok(())
+    }
+
+    /// Sends image header and data once, returns [MirajazzError::ChecksumMismatch] if device
+    /// rejected the image
+    async fn send_image_attempt(
+        &self,
+        key: u8,
+        header: &[u8],
+        image_data: &[u8],
+        bytes_sent: &mut usize,
+    ) -> Result<(), MirajazzError> {
+        let _expected = self.acks.expect();
+
+        if self.protocol.image_framing() == ImageFraming::HeaderCommand {
+            self.write_packet(&self.extend_payload(header)?).await?;
+        }
+
+        self.write_image_data_reports(Some(key), image_data, bytes_sent)
+            .await?;
+
+        if self.image_checksum.is_none() {
+            if let Some(timeout) = self.ack_timeout {
+                self.await_ack(timeout).await?;
+            }
+
+            return Ok(());
+        }
+
+        if self.transfer_accepted(key).await? {
+            return Ok(());
+        }
+
+        self.stats.update(|stats| stats.checksum_mismatches += 1);
+
+        warn!(
+            "Device rejected image of key {} because of checksum mismatch",
+            key
+        );
+
+        Err(MirajazzError::ChecksumMismatch { key })
+    }
+
+    /// Writes image data to device, changes must be flushed with [Device::flush] before
+    /// they will appear on the device!
+    pub async fn write_image(&self, key: u8, image_data: &[u8]) -> Result<(), MirajazzError> {
+        self.conversions.lock().await.remove(&key);
+
+        self.image_cache
+            .lock()
+            .await
+            .insert(key, Arc::from(image_data));
+
+        Ok(())
+    }
+
+    /// Writes converted image, keeping what the conversion did for [Device::last_flush_report]
+    async fn write_converted(&self, key: u8, converted: ConvertedImage) {
+        self.conversions.lock().await.insert(key, converted.meta);
+        self.image_cache.lock().await.insert(key, converted.bytes);
+    }
+
+    /// Waits for the device to accept or reject checksummed image transfer,
+    /// firmwares that don't respond are assumed to accept it
+    async fn transfer_accepted(&self, key: u8) -> Result<bool, MirajazzError> {
+        let deadline = Instant::now() + CHECKSUM_ACK_TIMEOUT;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let report = match self.read_reply(remaining).await? {
+                Some(report) => report,
+                None => break,
+            };
+
+            if is_commit_ack(&report) {
+                return Ok(true);
+            }
+
+            if is_transfer_nak(&report, key) {
+                return Ok(false);
+            }
+
+            self.queued_reports.lock().await.push_back(report);
+        }
+
+        Ok(true)
+    }
+
+    /// Waits for the device to acknowledge the last write, see [Device::with_ack_timeout]
+    async fn await_ack(&self, timeout: Duration) -> Result<(), MirajazzError> {
+        let deadline = Instant::now() + timeout;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let report = match self.read_reply(remaining).await? {
+                Some(report) => report,
+                None => break,
+            };
+
+            if is_commit_ack(&report) {
+                return Ok(());
+            }
+
+            if report.starts_with(&NAK) || parse_render_error(&report).is_some() {
+                return Err(MirajazzError::Nack { reply: report });
+            }
+
+            self.queued_reports.lock().await.push_back(report);
+        }
+
+        Err(MirajazzError::AckTimeout)
+    }
+
+    /// Returns encoded image currently displayed on the button, as it was last flushed
+    ///
+    /// Data is shared with the library, no copy is made
+    pub async fn get_button_image(&self, key: u8) -> Option<Arc<[u8]>> {
+        self.remembered_images.lock().await.get(&key).cloned()
+    }
+
+    /// Returns total size of encoded images held by the library, pending and displayed ones
+    ///
+    /// Images shared between pending and displayed ones are only counted once
+    pub async fn image_bytes_held(&self) -> usize {
+        let cache = self.image_cache.lock().await;
+        let remembered = self.remembered_images.lock().await;
+
+        let mut seen = HashSet::new();
+
+        cache
+            .values()
+            .chain(remembered.values())
+            .filter(|image_data| seen.insert(image_data.as_ptr()))
+            .map(|image_data| image_data.len())
+            .sum()
+    }
+
+    /// Streams pre-encoded image of provided length to the button and commits it right away,
+    /// holding only a single packet in memory
+    ///
+    /// For very large images, like the ones of LCD strips, on memory constrained hosts.
+    /// Image data isn't cached or remembered, so it's not restored by [Device::wake].
+    /// Returns [MirajazzError::IoError] if the source ends before `length` bytes were read
+    pub async fn send_image_streamed<R: AsyncRead + Unpin>(
+        &self,
+        key: u8,
+        length: usize,
+        mut image_data: R,
+    ) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        self.image_cache.lock().await.remove(&key);
+        self.remembered_images.lock().await.remove(&key);
+
+        let transfer = self.transfer_lock.lock(&self.stats).await;
+
+        // Checksum needs the whole image, so it isn't sent for streamed ones
+        if self.protocol.image_framing() == ImageFraming::HeaderCommand {
+            let buf = self.protocol.encode(&Command::ImageHeader {
+                key,
+                length,
+                checksum: 0,
+            });
+
+            self.write_packet(&self.extend_payload(&buf)?).await?;
+        }
+
+        let image_report_length = self.packet_size() + 1;
+        let image_report_header_length = self.image_packet_header(Some(key), 0, false, 0).len();
+        let image_report_payload_length = image_report_length - image_report_header_length;
+
+        let mut page_number = 0;
+        let mut bytes_remaining = length;
+        let mut buf = vec![0u8; image_report_length];
+
+        while bytes_remaining > 0 {
+            let this_length = bytes_remaining.min(image_report_payload_length);
+            let header = self.image_packet_header(
+                Some(key),
+                page_number,
+                this_length == bytes_remaining,
+                this_length,
+            );
+
+            // Padding is zeroed for the last packet
+            buf[..header.len()].copy_from_slice(&header);
+            buf[header.len() + this_length..].fill(0);
+            image_data
+                .read_exact(&mut buf[header.len()..header.len() + this_length])
+                .await?;
+
+            self.write_packet(&buf).await?;
+
+            bytes_remaining -= this_length;
+            page_number += 1;
+        }
+
+        drop(transfer);
+
+        self.commit().await
+    }
+
+    /// Reads back image currently displayed on the button, key is logical like in [Device::set_button_image]
+    ///
+    /// Returned data is validated to decode as the mode of provided image format.
+    /// Returns [None] if device didn't respond in time,
+    /// returns [MirajazzError::UnsupportedOperation] if device can't read back images.
+    /// Input reports received while reading are passed to the reader
+    pub async fn read_button_image(
+        &self,
+        key: u8,
+        image_format: ImageFormat,
+    ) -> Result<Option<Vec<u8>>, MirajazzError> {
+        let opcode = match self.image_readback_command {
+            Some(opcode) => opcode,
+            None => return Err(MirajazzError::UnsupportedOperation),
+        };
+
+        if key as usize >= self.key_count || key == u8::MAX {
+            return Err(MirajazzError::InvalidKeyIndex);
+        }
+
+        let key = self.physical_key(key);
+
+        self.initialize().await?;
+
+        self.write_command(Command::ReadImage { opcode, key })
+            .await?;
+
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+
+        // Response header carries total length of the image at the same place as BAT does
+        let header = loop {
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) => remaining,
+                None => return Ok(None),
+            };
+
+            let report = match self.read_report(remaining).await? {
+                Some(report) => report,
+                None => return Ok(None),
+            };
+
+            if is_image_readback_header(&report, opcode) {
+                break report;
+            }
+
+            self.queued_reports.lock().await.push_back(report);
+        };
+
+        let length = ((header[10] as usize) << 8) | header[11] as usize;
+        let mut collector = ChunkCollector::new(length);
+
+        while !collector.is_complete() {
+            let report = match self.read_report(RESPONSE_TIMEOUT).await? {
+                Some(report) => report,
+                None => return Ok(None),
+            };
+
+            if is_device_report(&report) {
+                self.queued_reports.lock().await.push_back(report);
+            } else {
+                collector.push(&report);
+            }
+        }
+
+        let image_data = collector.into_inner();
+
+        validate_image_data(image_format.mode, &image_data)?;
+
+        Ok(Some(image_data))
+    }
+
+    /// Returns rotation of the device, set by [Device::set_orientation]
+    pub fn orientation(&self) -> Orientation {
+        *self
+            .orientation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns physical index of the key at provided logical index, see [Device::set_orientation]
+    pub fn physical_key(&self, key: u8) -> u8 {
+        match &self.geometry {
+            Some(geometry) => geometry
+                .physical_key(key, self.orientation())
+                .unwrap_or(key),
+            None => key,
+        }
+    }
+
+    /// Returns logical index of the key at provided physical index, see [Device::set_orientation]
+    pub fn logical_key(&self, key: u8) -> u8 {
+        self.logical_keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(key as usize)
+            .copied()
+            .unwrap_or(key)
+    }
+
+    /// Rotates the layout for device mounted in a different orientation, and shows displayed
+    /// images at their new places right away
+    ///
+    /// Keys passed to [Device::set_button_image], [Device::set_zoned_button_image] and
+    /// [Device::clear_button_image], and keys in reader updates become logical, so key 0 is always
+    /// at the top left. Images are rotated to appear upright. Lower level methods like
+    /// [Device::write_image] keep using physical indices.
+    ///
+    /// Displayed images are decoded and encoded again with provided image format, or the format
+    /// of the key's zone if device has zones. Pending images are flushed first.
+    /// Returns [MirajazzError::UnsupportedOperation] if device geometry isn't known
+    pub async fn set_orientation(
+        &self,
+        orientation: Orientation,
+        image_format: ImageFormat,
+    ) -> Result<(), MirajazzError> {
+        let geometry = match &self.geometry {
+            Some(geometry) => geometry,
+            None => return Err(MirajazzError::UnsupportedOperation),
+        };
+
+        self.flush().await?;
+
+        let previous = self.orientation();
+
+        let remembered: Vec<(u8, Arc<[u8]>)> = self
+            .remembered_images
+            .lock()
+            .await
+            .iter()
+            .map(|(key, image_data)| (*key, image_data.clone()))
+            .collect();
+
+        // Recover images as they were passed by the user, before the layout changes
+        let mut images = Vec::with_capacity(remembered.len());
+
+        for (physical, image_data) in remembered {
+            let logical = (0..self.key_count as u8)
+                .find(|key| geometry.physical_key(*key, previous) == Some(physical));
+
+            let logical = match logical {
+                Some(logical) => logical,
+                None => continue,
+            };
+
+            let encoded_with = match self.zone_for_key(physical) {
+                Some(zone) => zone.image_format,
+                None => image_format,
+            };
+
+            let image = image::load_from_memory(&image_data)?;
+            let image = unrotate(undo_transform(image, encoded_with), previous);
+
+            images.push((logical, image));
+        }
+
+        self.clear_all_button_images().await?;
+
+        self.apply_orientation(geometry, orientation);
+
+        for (logical, image) in images {
+            match self.zone_for_key(self.physical_key(logical)) {
+                Some(_) => self.set_zoned_button_image(logical, image).await?,
+                None => self.set_button_image(logical, image_format, image).await?,
+            }
+        }
+
+        self.flush().await
+    }
+
+    /// Switches key mapping to provided orientation, without touching displayed images
+    fn apply_orientation(&self, geometry: &Geometry, orientation: Orientation) {
+        *self
+            .orientation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = orientation;
+
+        let mut logical_keys = vec![0; self.key_count];
+
+        for logical in 0..self.key_count as u8 {
+            if let Some(physical) = geometry.physical_key(logical, orientation) {
+                logical_keys[physical as usize] = logical;
+            }
+        }
+
+        *self
+            .logical_keys
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = match orientation {
+            Orientation::Normal => vec![],
+            _ => logical_keys,
+        };
+    }
+
+    /// Returns state of the device that can be reapplied with [Device::restore],
+    /// for example after the device reconnects
+    pub async fn snapshot(&self) -> DeviceSnapshot {
+        let mut images: Vec<(u8, Arc<[u8]>)> = self
+            .remembered_images
+            .lock()
+            .await
+            .iter()
+            .map(|(key, image_data)| (*key, image_data.clone()))
+            .collect();
+
+        images.sort_unstable_by_key(|(key, _)| *key);
+
+        DeviceSnapshot {
+            brightness: *self.brightness.lock().await,
+            orientation: self.orientation(),
+            images,
+        }
+    }
+
+    /// Reapplies state taken with [Device::snapshot]
+    ///
+    /// Initializes the device, sets brightness and orientation, sends images and commits them
+    pub async fn restore(&self, snapshot: &DeviceSnapshot) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        if let Some(brightness) = snapshot.brightness {
+            self.apply_brightness(brightness).await?;
+        }
+
+        if let Some(geometry) = &self.geometry {
+            self.apply_orientation(geometry, snapshot.orientation);
+        }
+
+        {
+            let mut cache = self.image_cache.lock().await;
+
+            for (key, image_data) in &snapshot.images {
+                cache.insert(*key, image_data.clone());
+            }
+        }
+
+        self.flush().await
+    }
+
+    /// Sets button's image to blank, changes must be flushed with [Device::flush] before
+    /// they will appear on the device!
+    ///
+    /// See [Device::with_clear_strategy] for how buttons are cleared
+    pub async fn clear_button_image(&self, key: u8) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        let key = match key {
+            0xff => key,
+            key => self.physical_key(key),
+        };
+
+        self.clear_physical_key(key).await
+    }
+
+    /// Clears image of the key by its physical index, or of every key for `0xff`
+    async fn clear_physical_key(&self, key: u8) -> Result<(), MirajazzError> {
+        if self.clear_strategy.sends_command() {
+            let command = if key == 0xff {
+                Command::ClearAll
+            } else {
+                Command::ClearKey(key)
+            };
+
+            self.write_command(command).await?;
+        }
+
+        self.image_cache.lock().await.remove(&key);
+
+        if key == 0xff {
+            self.remembered_images.lock().await.clear();
+        } else {
+            self.remembered_images.lock().await.remove(&key);
+        }
+
+        if let Some(image_format) = self.clear_strategy.black_image_format() {
+            let keys = if key == 0xff {
+                0..self.key_count as u8
+            } else {
+                key..key + 1
+            };
+
+            for key in keys {
+                self.write_black_image(key, image_format).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes black image to the button, using the format of the key's zone if device has zones
+    pub(crate) async fn write_black_image(
+        &self,
+        key: u8,
+        image_format: ImageFormat,
+    ) -> Result<(), MirajazzError> {
+        let image_format = self.check_image_format(key, image_format)?;
+        let (width, height) = image_format.size;
+
+        let image = DynamicImage::new_rgb8(width as u32, height as u32);
+        let image_data = convert_image_with_format(image_format, image).await?;
+
+        self.write_image(key, &image_data).await
+    }
+
+    /// Validates image format against the zone of the key or native resolution,
+    /// returns adapted format if device was marked with [Device::with_auto_adapt_images]
+    fn check_image_format(
+        &self,
+        key: u8,
+        image_format: ImageFormat,
+    ) -> Result<ImageFormat, MirajazzError> {
+        if !self.zones.is_empty() {
+            return match self.zone_for_key(key) {
+                Some(zone) if zone.image_format == image_format => Ok(image_format),
+                Some(zone) if self.auto_adapt_images => Ok(zone.image_format),
+                Some(_) => Err(MirajazzError::WrongZone),
+                None => Err(MirajazzError::InvalidKeyIndex),
+            };
+        }
+
+        self.check_native_size(image_format)
+    }
+
+    /// Validates image format against native resolution, returns adapted format if device
+    /// was marked with [Device::with_auto_adapt_images]
+    fn check_native_size(&self, image_format: ImageFormat) -> Result<ImageFormat, MirajazzError> {
+        let native = match self.native_image_size {
+            Some(native) => native,
+            None => return Ok(image_format),
+        };
+
+        if image_format.encoded_size() == native {
+            Ok(image_format)
+        } else if self.auto_adapt_images {
+            Ok(image_format.with_encoded_size(native))
+        } else {
+            Err(MirajazzError::FormatMismatch {
+                expected: native,
+                got: image_format.encoded_size(),
+            })
+        }
+    }
+
+    /// Sets blank images to every button, changes must be flushed with [Device::flush] before
+    /// they will appear on the device!
+    ///
+    /// Also resets [Device::last_flush_report]
+    pub async fn clear_all_button_images(&self) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        // Cleared before, so black images written by the clear strategy stay pending
+        self.image_cache.lock().await.clear();
+        *self.last_flush_report.lock().await = FlushReport::default();
+
+        self.clear_button_image(0xFF).await?;
+
+        if self.clear_strategy.sends_command() && self.protocol_version.requires_stp_after_clear() {
+            // Protocol v2/v3 requires STP to commit clearing the screen
+            self.write_command(Command::Commit).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sets specified button's image, changes must be flushed with [Device::flush] before
+    /// they will appear on the device!
+    ///
+    /// If device has zones, returns [MirajazzError::WrongZone] when image format
+    /// doesn't match the zone of the key. Otherwise, if native resolution is known, returns
+    /// [MirajazzError::FormatMismatch] when image size doesn't match it.
+    /// Use [Device::with_auto_adapt_images] to rescale images instead
+    pub async fn set_button_image(
+        &self,
+        key: u8,
+        image_format: ImageFormat,
+        image: DynamicImage,
+    ) -> Result<(), MirajazzError> {
+        let key = self.physical_key(key);
+        let image_format = self.check_image_format(key, image_format)?;
+
+        self.initialize().await?;
+
+        let image = upright(image, self.orientation());
+        let converted = convert_image(image_format, image).await?;
+
+        self.write_converted(key, converted).await;
+
+        Ok(())
+    }
+
+    /// Sets specified button's image using image format of the key's zone, changes must be
+    /// flushed with [Device::flush] before they will appear on the device!
+    pub async fn set_zoned_button_image(
+        &self,
+        key: u8,
+        image: DynamicImage,
+    ) -> Result<(), MirajazzError> {
+        let image_format = match self.zone_for_key(self.physical_key(key)) {
+            Some(zone) => zone.image_format,
+            None => return Err(MirajazzError::InvalidKeyIndex),
+        };
+
+        self.set_button_image(key, image_format, image).await
     }
 
-    /// Returns serial number of the device
-    pub fn serial_number(&self) -> &String {
-        &self.serial_number
-    }
+    /// Shows a page of images, sending only the keys that differ from what's displayed
+    ///
+    /// Keys which encoded image matches the displayed one are skipped, keys that had an image
+    /// but aren't on the page are cleared. Everything is committed with a single flush.
+    /// Images use provided image format, or the format of the key's zone if device has zones
+    pub async fn apply_page(
+        &self,
+        target: &[(u8, DynamicImage)],
+        image_format: ImageFormat,
+    ) -> Result<PageSummary, MirajazzError> {
+        self.initialize().await?;
 
-    pub fn supports_both_encoder_states(&self) -> bool {
-        self.supports_both_encoder_states
-    }
+        let orientation = self.orientation();
+        let mut summary = PageSummary::default();
+        let mut on_page = HashSet::new();
 
-    /// Initializes the device
-    async fn initialize(&self) -> Result<(), MirajazzError> {
-        if self.initialized.load(Ordering::Acquire) {
-            return Ok(());
+        for (key, image) in target {
+            let physical = self.physical_key(*key);
+
+            let image_format = match self.zone_for_key(physical) {
+                Some(zone) => zone.image_format,
+                None => self.check_image_format(physical, image_format)?,
+            };
+
+            let image = upright(image.clone(), orientation);
+            let converted = convert_image(image_format, image).await?;
+
+            on_page.insert(physical);
+
+            let displayed = self.remembered_images.lock().await.get(&physical).cloned();
+
+            if displayed.as_ref() == Some(&converted.bytes) {
+                // Drop image written earlier, so the displayed one stays
+                self.image_cache.lock().await.remove(&physical);
+                summary.skipped.push(*key);
+            } else {
+                self.write_converted(physical, converted).await;
+                summary.changed.push(*key);
+            }
         }
 
-        self.initialized.store(true, Ordering::Release);
+        let mut stale: Vec<u8> = self
+            .remembered_images
+            .lock()
+            .await
+            .keys()
+            .filter(|key| !on_page.contains(*key))
+            .copied()
+            .collect();
 
-        let mut buf = vec![0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x44, 0x49, 0x53];
-        self.write_extended_data(&mut buf).await?;
+        stale.sort_unstable();
 
-        let mut buf = vec![
-            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x4c, 0x49, 0x47, 0x00, 0x00, 0x00, 0x00,
-        ];
-        self.write_extended_data(&mut buf).await?;
+        for physical in stale {
+            self.clear_physical_key(physical).await?;
 
-        Ok(())
+            summary.cleared.push(self.logical_key(physical));
+        }
+
+        self.flush().await?;
+
+        if summary.changed.is_empty()
+            && !summary.cleared.is_empty()
+            && self.clear_strategy.sends_command()
+            && self.protocol_version.requires_stp_after_clear()
+        {
+            // Nothing was sent, so flush didn't commit the clears
+            self.write_command(Command::Commit).await?;
+        }
+
+        Ok(summary)
     }
 
-    /// Resets the device
-    pub async fn reset(&self) -> Result<(), MirajazzError> {
-        self.initialize().await?;
+    /// Shows startup images in one go: initializes the device, sets brightness, clears the screen,
+    /// writes and flushes provided images
+    ///
+    /// Images use provided image format, or the format of the key's zone if device has zones.
+    /// Safe to call before [Device::get_reader]. If device was configured with [Device::with_boot_delay],
+    /// waits for the device to finish booting first, and retries the transfer according to
+    /// [Device::with_retry_policy], waiting at least the boot delay between attempts
+    pub async fn show_splash(
+        &self,
+        images: &[(u8, DynamicImage)],
+        image_format: ImageFormat,
+        brightness: u8,
+    ) -> Result<(), MirajazzError> {
+        let delay = match self.boot_delay {
+            Some(delay) => delay,
+            None => return self.write_splash(images, image_format, brightness).await,
+        };
 
-        self.set_brightness(100).await?;
-        self.clear_all_button_images().await?;
+        time::sleep(delay).await;
 
-        Ok(())
+        let policy = RetryPolicy {
+            base_delay: self.retry_policy.base_delay.max(delay),
+            max_delay: self.retry_policy.max_delay.max(delay),
+            ..self.retry_policy
+        };
+
+        retry::with_policy(&policy, || {
+            self.write_splash(images, image_format, brightness)
+        })
+        .await
     }
 
-    /// Sets brightness of the device, value range is 0 - 100
-    pub async fn set_brightness(&self, percent: u8) -> Result<(), MirajazzError> {
+    async fn write_splash(
+        &self,
+        images: &[(u8, DynamicImage)],
+        image_format: ImageFormat,
+        brightness: u8,
+    ) -> Result<(), MirajazzError> {
         self.initialize().await?;
 
-        let percent = percent.clamp(0, 100);
+        self.set_brightness(brightness).await?;
+        self.clear_all_button_images().await?;
 
-        let mut buf = vec![
-            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x4c, 0x49, 0x47, 0x00, 0x00, percent,
-        ];
+        for (key, image) in images {
+            let image_format = match self.zone_for_key(*key) {
+                Some(zone) => zone.image_format,
+                None => image_format,
+            };
 
-        self.write_extended_data(&mut buf).await?;
+            self.set_button_image(*key, image_format, image.clone())
+                .await?;
+        }
 
-        Ok(())
+        self.flush().await
     }
 
-    /// Sets brightness of the knob LEDs, value range is 0 - 100
-    pub async fn set_led_brightness(&self, percent: u8) -> Result<(), MirajazzError> {
-        self.initialize().await?;
+    /// Uploads image that device shows by itself when it goes to sleep
+    ///
+    /// Image is written to device flash, so avoid calling this often.
+    /// Returns [MirajazzError::UnsupportedOperation] if device wasn't configured
+    /// with [Device::with_standby_image_command] or can't upload the boot logo, and
+    /// [MirajazzError::FormatMismatch] if image size doesn't match native resolution
+    pub async fn set_standby_image(
+        &self,
+        image_format: ImageFormat,
+        image: DynamicImage,
+    ) -> Result<(), MirajazzError> {
+        let command = self
+            .capabilities
+            .require_upload(self.standby_image_command)?;
 
-        let percent = percent.clamp(0, 100);
+        // Checked before anything is sent, so a wrong image never reaches the flash
+        let image_format = self.check_native_size(image_format)?;
 
-        let mut buf = vec![
-            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x4c, 0x42, 0x4c, 0x49, 0x47, percent,
-        ];
+        self.initialize().await?;
 
-        self.write_extended_data(&mut buf).await?;
+        let image_data = convert_image_with_format(image_format, image).await?;
 
-        Ok(())
+        self.upload(command, &image_data).await
     }
 
-    /// Sets the color of each knob LED individually.
-    /// `colors` must contain exactly one `[r, g, b]` entry per LED.
-    pub async fn set_led_colors(&self, colors: &[[u8; 3]]) -> Result<(), MirajazzError> {
+    /// Reverts standby image to the factory one
+    ///
+    /// Returns [MirajazzError::UnsupportedOperation] if device wasn't configured
+    /// with [Device::with_standby_image_command] or can't upload the boot logo
+    pub async fn clear_standby_image(&self) -> Result<(), MirajazzError> {
+        let command = self
+            .capabilities
+            .require_upload(self.standby_image_command)?;
+
         self.initialize().await?;
 
-        let mut buf = vec![
-            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x53, 0x45, 0x54, 0x4c, 0x42,
-        ];
+        self.upload(command, &[]).await
+    }
 
-        for [r, g, b] in colors {
-            buf.push(*r);
-            buf.push(*g);
-            buf.push(*b);
-        }
+    /// Writes image data to the flash slot, empty data resets the slot
+    async fn upload(&self, command: UploadCommand, image_data: &[u8]) -> Result<(), MirajazzError> {
+        let transfer = self.transfer_lock.lock(&self.stats).await;
 
-        self.write_extended_data(&mut buf).await?;
+        let buf = self.protocol.encode(&Command::Upload {
+            command,
+            length: image_data.len(),
+        });
 
-        Ok(())
+        self.write_packet(&self.extend_payload(&buf)?).await?;
+
+        self.write_image_data_reports(None, image_data, &mut 0)
+            .await?;
+
+        drop(transfer);
+
+        self.write_command(Command::Commit).await
     }
 
-    /// Writes raw image data to the device, not to be used directly
-    async fn send_image(&self, key: u8, image_data: &[u8]) -> Result<(), MirajazzError> {
-        let mut buf = vec![
-            0x00,
-            0x43,
-            0x52,
-            0x54,
-            0x00,
-            0x00,
-            0x42,
-            0x41,
-            0x54,
-            0x00,
-            0x00,
-            (image_data.len() >> 8) as u8,
-            image_data.len() as u8,
-            key + 1,
-        ];
-
-        self.write_extended_data(&mut buf).await?;
-
-        self.write_image_data_reports(image_data).await?;
+    /// Puts device to sleep, use [Device::wake] to wake it up
+    pub async fn sleep(&self) -> Result<(), MirajazzError> {
+        self.initialize().await?;
+
+        self.write_command(Command::Sleep).await?;
 
         Ok(())
     }
 
-    /// Writes image data to device, changes must be flushed with [Device::flush] before
-    /// they will appear on the device!
-    pub async fn write_image(&self, key: u8, image_data: &[u8]) -> Result<(), MirajazzError> {
-        self.image_cache
-            .lock()
-            .await
-            .insert(key, image_data.to_vec());
+    /// Wakes device up after [Device::sleep]
+    ///
+    /// Brightness is restored, and if device was marked with [Device::with_clears_on_sleep]
+    /// or its images don't persist across sleep, remembered images are sent again
+    pub async fn wake(&self) -> Result<(), MirajazzError> {
+        self.handshaken.store(false, Ordering::Release);
+        self.initialized.store(false, Ordering::Release);
+        self.initialize().await?;
+
+        let brightness = *self.brightness.lock().await;
+
+        if let Some(brightness) = brightness {
+            self.apply_brightness(brightness).await?;
+        }
+
+        if self.clears_on_sleep || !self.capabilities.images_persist_on_sleep {
+            self.restore_images().await?;
+        }
 
         Ok(())
     }
 
-    /// Sets button's image to blank, changes must be flushed with [Device::flush] before
-    /// they will appear on the device!
-    pub async fn clear_button_image(&self, key: u8) -> Result<(), MirajazzError> {
+    /// Turns the display off, keeping device awake and reporting input
+    ///
+    /// Use [Device::display_on] to turn it back on. See [Device::with_display_off_flush]
+    /// for what flushes do in the meantime
+    pub async fn display_off(&self) -> Result<(), MirajazzError> {
         self.initialize().await?;
 
-        let mut buf = vec![
-            0x00,
-            0x43,
-            0x52,
-            0x54,
-            0x00,
-            0x00,
-            0x43,
-            0x4c,
-            0x45,
-            0x00,
-            0x00,
-            0x00,
-            if key == 0xff { 0xff } else { key + 1 },
-        ];
-
-        self.write_extended_data(&mut buf).await?;
+        // Same as the first half of shutdown, without putting the device to sleep
+        self.write_command(Command::Shutdown).await?;
 
-        self.image_cache.lock().await.remove(&key);
+        self.display_off.store(true, Ordering::Release);
 
         Ok(())
     }
 
-    /// Sets blank images to every button, changes must be flushed with [Device::flush] before
-    /// they will appear on the device!
-    pub async fn clear_all_button_images(&self) -> Result<(), MirajazzError> {
+    /// Turns the display back on after [Device::display_off]
+    ///
+    /// Brightness and remembered images are restored, pending images are left for the next flush
+    pub async fn display_on(&self) -> Result<(), MirajazzError> {
         self.initialize().await?;
 
-        self.clear_button_image(0xFF).await?;
+        let brightness = *self.brightness.lock().await;
 
-        if self.protocol_version >= 2 {
-            // Protocol v2/v3 requires STP to commit clearing the screen
-            let mut buf = vec![0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x53, 0x54, 0x50];
+        if let Some(brightness) = brightness {
+            self.apply_brightness(brightness).await?;
+        }
+
+        self.restore_images().await?;
 
-            self.write_extended_data(&mut buf).await?;
+        if self.display_on_commit {
+            self.write_command(Command::Commit).await?;
         }
 
-        self.image_cache.lock().await.clear();
+        self.display_off.store(false, Ordering::Release);
 
         Ok(())
     }
 
-    /// Sets specified button's image, changes must be flushed with [Device::flush] before
-    /// they will appear on the device!
-    pub async fn set_button_image(
-        &self,
-        key: u8,
-        image_format: ImageFormat,
-        image: DynamicImage,
-    ) -> Result<(), MirajazzError> {
-        self.initialize().await?;
+    /// Returns false if the display was turned off with [Device::display_off]
+    pub fn is_display_on(&self) -> bool {
+        !self.display_off.load(Ordering::Acquire)
+    }
 
-        let image_data = convert_image_with_format(image_format, image).await?;
+    /// Sends remembered images to the device again
+    async fn restore_images(&self) -> Result<(), MirajazzError> {
+        let remembered = self.remembered_images.lock().await;
 
-        self.write_image(key, &image_data).await?;
+        if remembered.is_empty() {
+            return Ok(());
+        }
 
-        Ok(())
-    }
+        for (key, image_data) in remembered.iter() {
+            self.send_image(*key, image_data).await?;
 
-    /// Puts device to sleep
-    pub async fn sleep(&self) -> Result<(), MirajazzError> {
-        self.initialize().await?;
+            task::yield_now().await;
+        }
 
-        let mut buf = vec![0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x48, 0x41, 0x4e];
-        self.write_extended_data(&mut buf).await?;
+        self.write_command(Command::Commit).await?;
 
         Ok(())
     }
 
     /// Make periodic events to the device, to keep it alive
+    ///
+    /// Doesn't touch the display, so it's safe for apps that only read input
     pub async fn keep_alive(&self) -> Result<(), MirajazzError> {
-        self.initialize().await?;
-
-        let mut buf = vec![
-            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x43, 0x4F, 0x4E, 0x4E, 0x45, 0x43, 0x54,
-        ];
+        self.handshake().await?;
 
-        self.write_extended_data(&mut buf).await?;
+        self.write_command(Command::KeepAlive).await?;
 
         Ok(())
     }
 
     /// Shutdown the device
     pub async fn shutdown(&self) -> Result<(), MirajazzError> {
-        self.initialize().await?;
+        self.brightness_throttle.cancel();
 
-        let mut buf = vec![
-            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x43, 0x4c, 0x45, 0x00, 0x00, 0x44, 0x43,
-        ];
-        self.write_extended_data(&mut buf).await?;
+        self.initialize().await?;
 
-        let mut buf = vec![0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x48, 0x41, 0x4E];
-        self.write_extended_data(&mut buf).await?;
+        self.write_command(Command::Shutdown).await?;
+        self.write_command(Command::Sleep).await?;
 
         Ok(())
     }
 
     /// Flushes written images, updating displays
+    ///
+    /// Images identical to the ones already displayed are skipped. If some images fail to send,
+    /// the rest are still flushed, failed ones stay pending and the first error is returned.
+    /// See [Device::last_flush_report] for per-key outcomes
+    ///
+    /// Yields to the executor between keys, so a reader running concurrently keeps receiving
+    /// input while images stream. Input latency grows by at most a single key transfer
     pub async fn flush(&self) -> Result<(), MirajazzError> {
+        self.flush_keys(None).await
+    }
+
+    /// Flushes written image of a single key, leaving other pending images in place
+    pub async fn flush_key(&self, key: u8) -> Result<(), MirajazzError> {
+        self.flush_keys(Some(key)).await
+    }
+
+    /// Returns keys that have written images which weren't flushed yet, ordered by key
+    pub async fn pending_keys(&self) -> Vec<u8> {
+        let mut keys: Vec<u8> = self.image_cache.lock().await.keys().copied().collect();
+        keys.sort_unstable();
+
+        keys
+    }
+
+    /// Returns per-key outcomes of the last flush that had pending images
+    pub async fn last_flush_report(&self) -> FlushReport {
+        self.last_flush_report.lock().await.clone()
+    }
+
+    /// Flushes all pending images, or only the specified key's one
+    async fn flush_keys(&self, only_key: Option<u8>) -> Result<(), MirajazzError> {
+        let started = Instant::now();
+
+        if !self.is_display_on() {
+            match self.display_off_flush {
+                DisplayOffFlush::Queue => return Ok(()),
+                DisplayOffFlush::Reenable => self.display_on().await?,
+            }
+        }
+
         let mut cache = self.image_cache.lock().await;
 
         self.initialize().await?;
 
-        if cache.is_empty() {
+        let mut keys: Vec<u8> = match only_key {
+            Some(key) if cache.contains_key(&key) => vec![key],
+            Some(_) => vec![],
+            None => cache.keys().copied().collect(),
+        };
+
+        if keys.is_empty() {
             return Ok(());
         }
 
-        for (key, image_data) in cache.drain() {
-            self.send_image(key, &image_data).await?;
+        keys.sort_unstable();
+
+        let mut remembered = self.remembered_images.lock().await;
+        let mut report = FlushReport::default();
+        let mut first_error = None;
+        let mut sent_any = false;
+
+        for key in keys {
+            let image_data = match cache.remove(&key) {
+                Some(image_data) => image_data,
+                None => continue,
+            };
+
+            if remembered.get(&key) == Some(&image_data) {
+                report.keys.push((key, KeyFlushOutcome::SkippedIdentical));
+                continue;
+            }
+
+            let mut bytes_sent = 0;
+
+            match self
+                .send_image_tracked(key, &image_data, &mut bytes_sent)
+                .await
+            {
+                Ok(()) => {
+                    sent_any = true;
+                    remembered.insert(key, image_data);
+                    report.keys.push((key, KeyFlushOutcome::Sent));
+
+                    if let Some(meta) = self.conversions.lock().await.remove(&key) {
+                        report.conversions.push((key, meta));
+                    }
+                }
+                Err(err) => {
+                    cache.insert(key, image_data);
+                    let outcome = KeyFlushOutcome::Failed {
+                        error: err.to_string(),
+                        bytes_sent,
+                    };
+
+                    report.keys.push((key, outcome));
+
+                    if first_error.is_none() {
+                        first_error = Some(err);
+                    }
+                }
+            }
+
+            // Let reader tasks drain input between keys
+            task::yield_now().await;
+        }
+
+        drop(remembered);
+
+        let stp_result = if sent_any {
+            self.commit().await
+        } else {
+            Ok(())
+        };
+
+        report.finished_at = Some(SystemTime::now());
+        *self.last_flush_report.lock().await = report;
+
+        self.stats
+            .update(|stats| stats.flush.record(started.elapsed()));
+
+        match first_error {
+            Some(err) => Err(err),
+            None => stp_result,
+        }
+    }
+
+    /// Commits sent images, waiting for acknowledgment if device was configured
+    /// with [Device::with_commit_ack_timeout] or [Device::with_ack_timeout]
+    async fn commit(&self) -> Result<(), MirajazzError> {
+        let _expected = self.acks.expect();
+        self.write_command(Command::Commit).await?;
+
+        if let Some(timeout) = self.ack_timeout {
+            let result = self.await_ack(timeout).await;
+
+            if let Err(MirajazzError::AckTimeout) = result {
+                self.stats.update(|stats| stats.commit_ack_timeouts += 1);
+            }
+
+            return result;
+        }
+
+        let timeout = match self.commit_ack_timeout {
+            Some(timeout) => timeout,
+            None => return Ok(()),
+        };
+
+        let deadline = Instant::now() + timeout;
+
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            let report = match self.read_reply(remaining).await? {
+                Some(report) => report,
+                None => break,
+            };
+
+            if is_commit_ack(&report) {
+                return Ok(());
+            }
+
+            if let Some((code, key)) = parse_render_error(&report) {
+                return match key {
+                    Some(key) if self.resend_on_render_error => {
+                        warn!("Device failed to render key {}, sending it again", key);
+
+                        self.resend_key(key).await
+                    }
+                    _ => Err(MirajazzError::DeviceReportedError { code }),
+                };
+            }
+
+            self.queued_reports.lock().await.push_back(report);
         }
 
-        let mut buf = vec![0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x53, 0x54, 0x50];
-        self.write_extended_data(&mut buf).await?;
+        self.stats.update(|stats| stats.commit_ack_timeouts += 1);
+
+        warn!("Device didn't acknowledge the commit in time");
 
         Ok(())
     }
 
+    /// Sends remembered image of the key again and commits it, for recovering from
+    /// [crate::types::DeviceNotification::RenderError]
+    ///
+    /// Does nothing if there's no remembered image for the key
+    pub async fn resend_key(&self, key: u8) -> Result<(), MirajazzError> {
+        let image_data = self.remembered_images.lock().await.get(&key).cloned();
+
+        let image_data = match image_data {
+            Some(image_data) => image_data,
+            None => return Ok(()),
+        };
+
+        self.send_image(key, &image_data).await?;
+
+        self.write_command(Command::Commit).await
+    }
+
     /// Returns button state reader for this device
     ///
     /// Accepts function pointer for a function that maps raw device inputs to [DeviceInput]
+    ///
+    /// Reading input never initializes the display, so screen managed by other software is left untouched
+    ///
+    /// Returns [MirajazzError::NoKeys] if device was connected with zero key count
     pub fn get_reader(
         &self,
         process_input: fn(u8, u8) -> Result<DeviceInput, MirajazzError>,
-    ) -> Arc<DeviceStateReader> {
-        #[allow(clippy::arc_with_non_send_sync)]
-        Arc::new(DeviceStateReader {
+    ) -> Result<Arc<DeviceStateReader>, MirajazzError> {
+        self.reader_with(InputParser::Key(process_input))
+    }
+
+    /// Reads input if some is available right away, without waiting and without an async context,
+    /// for draining input from external event loops
+    ///
+    /// Returns [None] immediately if there's no report. Input isn't diffed against button states,
+    /// use [DeviceStateReader::try_read] for that
+    ///
+    /// Returns [MirajazzError::NoKeys] if device was connected with zero key count
+    pub fn try_read_input(
+        &self,
+        process_input: fn(u8, u8) -> Result<DeviceInput, MirajazzError>,
+    ) -> Result<Option<DeviceInput>, MirajazzError> {
+        let process_input = InputParser::Key(process_input);
+
+        self.reader_with(process_input)?
+            .try_read_input(process_input)
+    }
+
+    /// Returns button state reader for this device, that passes whole input reports for processing
+    ///
+    /// Useful for devices that report multiple inputs at once, for example simultaneous twists
+    /// of several encoders can be returned as a single [DeviceInput::EncoderTwist]
+    ///
+    /// Returns [MirajazzError::NoKeys] if device was connected with zero key count
+    pub fn get_report_reader(
+        &self,
+        process_report: fn(&[u8]) -> Result<DeviceInput, MirajazzError>,
+    ) -> Result<Arc<DeviceStateReader>, MirajazzError> {
+        self.reader_with(InputParser::Report(process_report))
+    }
+
+    /// Returns button state reader for devices that report state of every button as a bitmap
+    ///
+    /// Bitmaps always carry both pressed and released keys, so events are produced by comparing
+    /// consecutive reports, regardless of [Device::with_supports_both_keypress_states]
+    ///
+    /// Returns [MirajazzError::NoKeys] if device was connected with zero key count
+    pub fn get_bitmap_reader(
+        &self,
+        bitmap: ButtonBitmap,
+    ) -> Result<Arc<DeviceStateReader>, MirajazzError> {
+        self.reader_with(InputParser::Bitmap(bitmap))
+    }
+
+    fn reader_with(
+        &self,
+        process_input: InputParser,
+    ) -> Result<Arc<DeviceStateReader>, MirajazzError> {
+        if self.key_count == 0 {
+            return Err(MirajazzError::NoKeys);
+        }
+
+        Ok(Arc::new(DeviceStateReader {
             protocol_version: self.protocol_version,
-            supports_both_keypress_states: self.supports_both_keypress_states,
+            input_report_size: self.input_report_size,
+            // Bitmaps carry released keys too
+            supports_both_keypress_states: self.supports_both_keypress_states
+                || matches!(process_input, InputParser::Bitmap(_)),
             supports_both_encoder_states: self.supports_both_encoder_states,
             reader: self.reader.clone(),
-            states: Mutex::new(DeviceState {
+            writer: self.writer.clone(),
+            transfer_lock: self.transfer_lock.clone(),
+            packet_size: self.packet_size.clone(),
+            input_mode: self.input_mode.clone(),
+            read_policy: self.read_policy,
+            states: std::sync::Mutex::new(DeviceState {
                 buttons: vec![false; self.key_count],
                 encoders: vec![false; self.encoder_count],
             }),
             process_input,
-        })
+            stats: self.stats.clone(),
+            queued_reports: self.queued_reports.clone(),
+            encoder_mode: self.encoder_mode.clone(),
+            pulses: std::sync::Mutex::new(HashMap::new()),
+            logical_keys: self.logical_keys.clone(),
+            key_codes: self.key_codes.clone(),
+            capability_events: self.capability_events,
+            acks: self.acks.clone(),
+            virtual_keys: self.virtual_keys.clone(),
+            virtual_state: std::sync::Mutex::new(VirtualKeyState::default()),
+            pending_read: self.pending_read.clone(),
+        }))
     }
 
     /// Splits image data into chunks and writes them separately, not to be used directly
-    async fn write_image_data_reports(&self, image_data: &[u8]) -> Result<(), MirajazzError> {
-        let image_report_length = self.packet_size + 1;
-        let image_report_header_length = 1;
+    async fn write_image_data_reports(
+        &self,
+        key: Option<u8>,
+        image_data: &[u8],
+        sent: &mut usize,
+    ) -> Result<(), MirajazzError> {
+        let image_report_length = self.packet_size() + 1;
+        let image_report_header_length = self.image_packet_header(key, 0, false, 0).len();
         let image_report_payload_length = image_report_length - image_report_header_length;
 
         let mut page_number = 0;
@@ -617,14 +3649,20 @@ impl Device {
 
             // Header
             buf.clear();
-            buf.push(0x00);
+            buf.extend(self.image_packet_header(
+                key,
+                page_number,
+                this_length == bytes_remaining,
+                this_length,
+            ));
             buf.extend(&image_data[bytes_sent..bytes_sent + this_length]);
 
             // Adding padding
             buf.resize(image_report_length, 0);
 
-            self.write_data(&buf).await?;
+            self.write_packet(&buf).await?;
 
+            *sent += this_length;
             bytes_remaining -= this_length;
             page_number += 1;
         }
@@ -632,39 +3670,144 @@ impl Device {
         Ok(())
     }
 
+    /// Returns header of image data packet, including report id
+    ///
+    /// Data without a key, like flash uploads, is always sent without packet headers
+    fn image_packet_header(
+        &self,
+        key: Option<u8>,
+        page: usize,
+        is_last: bool,
+        length: usize,
+    ) -> Vec<u8> {
+        match (self.protocol.image_framing(), key) {
+            (ImageFraming::HeaderCommand, _) | (_, None) => vec![0x00],
+            (ImageFraming::PerPacketHeader, Some(key)) => {
+                let mut header = vec![0x00];
+                header.extend(legacy_packet_header(key, page, is_last, length));
+                header
+            }
+        }
+    }
+
+    /// Returns amount of writes waiting for their turn, see [TransferLock] for the ordering
+    pub fn transfer_queue_depth(&self) -> usize {
+        self.transfer_lock.queue_depth()
+    }
+
     /// Writes data to device
+    ///
+    /// Writes are sent in the order they were issued, see [TransferLock]
     pub async fn write_data(&self, payload: &[u8]) -> Result<(), MirajazzError> {
-        Ok(self
-            .writer
-            .lock()
+        let _transfer = self.transfer_lock.lock(&self.stats).await;
+
+        self.write_packet(payload).await
+    }
+
+    /// Writes single packet to device, caller must hold the transfer lock
+    async fn write_packet(&self, payload: &[u8]) -> Result<(), MirajazzError> {
+        let mut writer = self.writer.lock().await;
+
+        let started = Instant::now();
+
+        writer.write_output_report(payload).await?;
+
+        self.stats
+            .update(|stats| stats.packet_write.record(started.elapsed()));
+
+        Ok(())
+    }
+
+    /// Reads single input report from the device, returns [None] if timeout was reached
+    pub(crate) async fn read_report(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, MirajazzError> {
+        // Reader may be held by a task waiting for input, which counts towards the timeout too
+        self.pending_read
+            .read(&self.reader, self.input_report_size, Some(timeout))
+            .await
+    }
+
+    /// Reads reply to the last write, which may have been received by a reader instead,
+    /// returns [None] if timeout was reached
+    async fn read_reply(&self, timeout: Duration) -> Result<Option<Vec<u8>>, MirajazzError> {
+        if let Some(report) = self.acks.take() {
+            return Ok(Some(report));
+        }
+
+        self.read_report(timeout)
+            .or(async { Ok(time::timeout(timeout, self.acks.wait()).await.ok()) })
             .await
-            .write_output_report(&payload)
-            .await?)
     }
 
     /// Writes data to device extending payload to the required size
-    pub async fn write_extended_data(&self, payload: &mut Vec<u8>) -> Result<(), MirajazzError> {
-        payload.resize(1 + self.packet_size, 0);
+    ///
+    /// Payload itself is left untouched, so the same command can be sent to devices with different packet sizes.
+    /// Payload must start with report id 0x00 and fit into a single packet, including the report id
+    pub async fn write_extended_data(&self, payload: &[u8]) -> Result<(), MirajazzError> {
+        self.write_data(&self.extend_payload(payload)?).await
+    }
+
+    /// Writes command to the device
+    pub async fn write_command(&self, command: Command<'_>) -> Result<(), MirajazzError> {
+        self.write_extended_data(&self.protocol.encode(&command))
+            .await
+    }
 
-        self.write_data(payload).await
+    /// Copies payload extending it to the required size
+    ///
+    /// Returns [MirajazzError::PayloadTooLarge] if payload doesn't fit into a single packet,
+    /// and [MirajazzError::MissingReportId] if it doesn't start with report id
+    fn extend_payload(&self, payload: &[u8]) -> Result<Vec<u8>, MirajazzError> {
+        extend_payload(payload, self.packet_size())
     }
 
     /// Set the device mode, for some devices it's required to set the device to the correct mode before sending any other command
     pub async fn set_mode(&self, mode: u8) -> Result<(), MirajazzError> {
-        let mut buf = vec![
-            0x00,
-            0x43,
-            0x52,
-            0x54,
-            0x00,
-            0x00,
-            0x4D,
-            0x4F,
-            0x44,
-            0x00,
-            0x00,
-            0x30 + mode,
-        ];
-        self.write_extended_data(&mut buf).await
+        self.write_command(Command::Mode(mode)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_serial_trims_nul_padding() {
+        assert_eq!(normalize_serial("355499441494\0\0\0\0"), "355499441494");
+        assert_eq!(normalize_serial("\0 ab12cd \0"), "AB12CD");
+        assert_eq!(normalize_serial("\0\0\0"), "");
+    }
+
+    #[test]
+    fn normalize_serial_uppercases_hex_only() {
+        assert_eq!(normalize_serial("a1b2c3"), "A1B2C3");
+        assert_eq!(normalize_serial("A1B2C3"), "A1B2C3");
+        assert_eq!(normalize_serial("abc-123"), "abc-123");
+        assert_eq!(normalize_serial("Mirabox_ab12"), "Mirabox_ab12");
+    }
+
+    #[test]
+    fn serial_match_ignores_padding_and_case() {
+        let exact = SerialMatch::Exact("a1b2c3".to_string());
+
+        assert!(exact.matches("A1B2C3\0\0"));
+        assert!(exact.matches("a1b2c3"));
+        assert!(!exact.matches("A1B2C4"));
+        assert!(!exact.matches("A1B2C3D4"));
+    }
+
+    #[test]
+    fn serial_prefix_ignores_padding_and_case() {
+        let prefix = SerialMatch::Prefix("a1b2".to_string());
+
+        assert!(prefix.matches("A1B2C3\0\0"));
+        assert!(prefix.matches("a1b2-rev2"));
+        assert!(!prefix.matches("A1B"));
+        assert!(!prefix.matches("B1B2C3"));
+
+        // Hex prefix of a serial that isn't all hex is normalized differently
+        assert!(SerialMatch::Prefix("ab".to_string()).matches("abc-123"));
     }
 }
@@ -7,6 +7,8 @@ use std::{
 use async_hid::HidError;
 use image::ImageError;
 
+use crate::types::DeviceId;
+
 /// Errors that can occur while working with devices
 #[derive(Debug)]
 pub enum MirajazzError {
@@ -19,12 +21,35 @@ pub enum MirajazzError {
     /// Invalid device provided
     InvalidDeviceError,
 
+    /// Device is already connected by this process
+    AlreadyConnected(DeviceId),
+
+    /// No device of a known model matches the query, with `vid:pid` of devices that did match
+    NoKnownDevice { found: Vec<String> },
+
+    /// Device is present, but another application holds it open
+    DeviceBusy {
+        vid: u16,
+        pid: u16,
+        serial: Option<String>,
+    },
+
+    /// Provided configuration doesn't match the known model of the device
+    ConfigMismatch {
+        device: DeviceId,
+        expected: String,
+        got: String,
+    },
+
     /// HidApi error
     HidError(HidError),
 
     /// Failed to encode image
     ImageError(ImageError),
 
+    /// Failed to read image data from the source
+    IoError(std::io::Error),
+
     /// Reader mutex was poisoned
     PoisonError,
 
@@ -34,6 +59,37 @@ pub enum MirajazzError {
     /// Key index is invalid
     InvalidKeyIndex,
 
+    /// Encoder index is invalid
+    InvalidEncoderIndex,
+
+    /// Brightness is out of 0 - 100 range
+    InvalidBrightness,
+
+    /// Image format doesn't match the zone of the key
+    WrongZone,
+
+    /// Geometry doesn't match key or encoder count
+    InvalidGeometry,
+
+    /// Device query string couldn't be parsed, with the reason
+    InvalidQuery(String),
+
+    /// Key code translation table maps several codes to the same key
+    DuplicateKeyCode { key: u8 },
+
+    /// Image size doesn't match native resolution of the device, sizes are after rotation
+    FormatMismatch {
+        expected: (usize, usize),
+        got: (usize, usize),
+    },
+
+    /// Device was declared without any keys
+    NoKeys,
+
+    /// Device reported input of the kind it was declared not to have,
+    /// usually means that key or encoder count is configured wrong
+    UnexpectedInput,
+
     /// Unrecognized Product ID
     UnrecognizedPID,
 
@@ -42,22 +98,113 @@ pub enum MirajazzError {
 
     /// Device sent unexpected data
     BadData,
+
+    /// Device didn't become ready in time
+    NotReady,
+
+    /// Device reported an error with provided code
+    DeviceReportedError { code: u8 },
+
+    /// Auxiliary interface of the device wasn't opened
+    NoAuxInterface,
+
+    /// Device kept rejecting image of the key because of checksum mismatch
+    ChecksumMismatch { key: u8 },
+
+    /// Device answered a write with something other than acknowledgment, with the raw reply
+    Nack { reply: Vec<u8> },
+
+    /// Device didn't acknowledge a write in time
+    AckTimeout,
+
+    /// Payload doesn't fit into a single packet, sizes include report id
+    PayloadTooLarge { size: usize, max: usize },
+
+    /// Payload doesn't start with report id 0x00
+    MissingReportId,
+
+    /// Numeric protocol version is not known to the library
+    UnsupportedProtocolVersion(usize),
+
+    /// Operation on the device didn't finish in time, see [crate::manager::DeviceManager::for_each]
+    OperationTimeout,
 }
 
 impl Display for MirajazzError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            MirajazzError::DeviceBusy { vid, pid, serial } => write!(
+                f,
+                "Device {:04x}:{:04x} ({}) is busy, probably another application holds it open",
+                vid,
+                pid,
+                serial.as_deref().unwrap_or("no serial")
+            ),
+            MirajazzError::NoKnownDevice { found } if found.is_empty() => {
+                write!(f, "No connected device matches the query")
+            }
+            MirajazzError::NoKnownDevice { found } => write!(
+                f,
+                "No known model among matching devices, connect manually with Device::connect_with. Found: {}",
+                found.join(", ")
+            ),
+            _ => write!(f, "{:?}", self),
+        }
     }
 }
 
 impl Error for MirajazzError {}
 
+impl MirajazzError {
+    /// Checks if the operation may succeed when tried again, without changing anything
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MirajazzError::HidError(error) => {
+                !matches!(error, HidError::Disconnected | HidError::NotConnected)
+            }
+            MirajazzError::DeviceBusy { .. }
+            | MirajazzError::BadData
+            | MirajazzError::NotReady
+            | MirajazzError::DeviceReportedError { .. }
+            | MirajazzError::ChecksumMismatch { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Checks if the error means that another application holds the device open
+///
+/// Platform backends report it differently, so this is a best effort
+pub fn is_busy_error(error: &HidError) -> bool {
+    let source = match error {
+        HidError::Other(source) => source,
+        _ => return false,
+    };
+
+    if let Some(error) = source.downcast_ref::<std::io::Error>() {
+        return error.kind() == std::io::ErrorKind::ResourceBusy;
+    }
+
+    let description = format!("{:?}", source);
+
+    // EBUSY on Linux, sharing violation on Windows, exclusive access on macOS
+    description.contains("EBUSY")
+        || description.contains("0x80070020")
+        || description.contains("e00002c5")
+}
+
 impl From<HidError> for MirajazzError {
     fn from(e: HidError) -> Self {
         Self::HidError(e)
     }
 }
 
+impl From<std::io::Error> for MirajazzError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
 impl From<ImageError> for MirajazzError {
     fn from(e: ImageError) -> Self {
         Self::ImageError(e)
@@ -2,21 +2,56 @@ use image::codecs::bmp::BmpEncoder;
 use image::codecs::jpeg::JpegEncoder;
 use image::imageops::FilterType;
 use image::{ColorType, DynamicImage, GenericImageView, ImageError};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use std::sync::Arc;
 
 use crate::error::MirajazzError;
-use crate::types::{ImageFormat, ImageMirroring, ImageMode, ImageRotation};
+use crate::types::{ImageFormat, ImageMirroring, ImageMode, ImageRotation, ImageTransform};
+
+/// What the conversion did to the image, for finding out why a key looks wrong
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ConversionMeta {
+    /// Size of the image passed for conversion
+    pub input_size: (u32, u32),
+    /// Size the image was resized to, before rotation
+    pub output_size: (u32, u32),
+    /// False if the image already had the right size
+    pub resized: bool,
+    /// Encoding of the result
+    pub mode: ImageMode,
+    /// Rotation and mirroring that were applied
+    pub transform: ImageTransform,
+    /// Length of the encoded image in bytes
+    pub encoded_len: usize,
+}
+
+/// Encoded image along with what the conversion did
+#[derive(Clone, Debug)]
+pub struct ConvertedImage {
+    pub bytes: Arc<[u8]>,
+    pub meta: ConversionMeta,
+}
 
 fn convert_image_with_format_impl(
     image_format: ImageFormat,
     image: DynamicImage,
-) -> Result<Vec<u8>, ImageError> {
+) -> Result<ConvertedImage, ImageError> {
     // Ensuring size of the image
     let (ws, hs) = image_format.size;
+    let input_size = image.dimensions();
+    let resized = input_size != (ws as u32, hs as u32);
 
-    let image = image.resize_exact(ws as u32, hs as u32, FilterType::Lanczos3);
+    let image = if resized {
+        image.resize_exact(ws as u32, hs as u32, FilterType::Lanczos3)
+    } else {
+        image
+    };
 
     // Applying rotation
-    let image = match image_format.rotation {
+    let image = match image_format.transform.rotation {
         ImageRotation::Rot0 => image,
         ImageRotation::Rot90 => image.rotate90(),
         ImageRotation::Rot180 => image.rotate180(),
@@ -24,7 +59,7 @@ fn convert_image_with_format_impl(
     };
 
     // Applying mirroring
-    let image = match image_format.mirror {
+    let image = match image_format.transform.mirror {
         ImageMirroring::None => image,
         ImageMirroring::X => image.fliph(),
         ImageMirroring::Y => image.flipv(),
@@ -34,21 +69,38 @@ fn convert_image_with_format_impl(
     let image_data = image.into_rgb8().to_vec();
 
     // Encoding image
-    match image_format.mode {
-        ImageMode::None => Ok(vec![]),
+    let buf = match image_format.mode {
+        ImageMode::None => vec![],
         ImageMode::BMP => {
             let mut buf = Vec::new();
             let mut encoder = BmpEncoder::new(&mut buf);
             encoder.encode(&image_data, ws as u32, hs as u32, ColorType::Rgb8.into())?;
-            Ok(buf)
+            buf
         }
         ImageMode::JPEG => {
             let mut buf = Vec::new();
-            let mut encoder = JpegEncoder::new_with_quality(&mut buf, 90);
+            let mut encoder =
+                JpegEncoder::new_with_quality(&mut buf, image_format.jpeg_options().quality);
             encoder.encode(&image_data, ws as u32, hs as u32, ColorType::Rgb8.into())?;
-            Ok(buf)
+            buf
         }
-    }
+    };
+
+    let meta = ConversionMeta {
+        input_size,
+        output_size: (ws as u32, hs as u32),
+        resized,
+        mode: image_format.mode,
+        transform: image_format.transform,
+        encoded_len: buf.len(),
+    };
+
+    log::debug!("Converted image: {:?}", meta);
+
+    Ok(ConvertedImage {
+        bytes: Arc::from(buf),
+        meta,
+    })
 }
 
 /// Converts image into image data depending on provided image format
@@ -56,9 +108,33 @@ pub async fn convert_image_with_format(
     image_format: ImageFormat,
     image: DynamicImage,
 ) -> Result<Vec<u8>, ImageError> {
+    let converted = convert_image(image_format, image).await?;
+
+    Ok(converted.bytes.to_vec())
+}
+
+/// Converts image into image data depending on provided image format, along with
+/// what the conversion did
+pub async fn convert_image(
+    image_format: ImageFormat,
+    image: DynamicImage,
+) -> Result<ConvertedImage, ImageError> {
     tokio::task::block_in_place(move || convert_image_with_format_impl(image_format, image))
 }
 
+/// Checks that image data received from the device can be decoded as provided image mode
+pub fn validate_image_data(mode: ImageMode, data: &[u8]) -> Result<(), MirajazzError> {
+    let format = match mode {
+        ImageMode::None => return Ok(()),
+        ImageMode::BMP => image::ImageFormat::Bmp,
+        ImageMode::JPEG => image::ImageFormat::Jpeg,
+    };
+
+    image::load_from_memory_with_format(data, format)?;
+
+    Ok(())
+}
+
 /// Rect to be used when trying to send image to lcd screen
 pub struct ImageRect {
     /// Width of the image
@@ -89,3 +165,70 @@ impl ImageRect {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(image_format: ImageFormat, size: (u32, u32)) -> ConvertedImage {
+        let image = DynamicImage::new_rgb8(size.0, size.1);
+
+        convert_image_with_format_impl(image_format, image).unwrap()
+    }
+
+    #[test]
+    fn meta_of_resized_image() {
+        let converted = convert(ImageFormat::jpeg((60, 60)), (100, 50));
+
+        assert_eq!(
+            converted.meta,
+            ConversionMeta {
+                input_size: (100, 50),
+                output_size: (60, 60),
+                resized: true,
+                mode: ImageMode::JPEG,
+                transform: ImageTransform::NONE,
+                encoded_len: converted.bytes.len(),
+            }
+        );
+        assert!(converted.meta.encoded_len > 0);
+    }
+
+    #[test]
+    fn meta_of_image_with_native_size() {
+        let image_format = ImageFormat {
+            mode: ImageMode::BMP,
+            transform: ImageTransform::new(ImageRotation::Rot90, ImageMirroring::X),
+            ..ImageFormat::jpeg((72, 72))
+        };
+
+        let converted = convert(image_format, (72, 72));
+
+        assert_eq!(
+            converted.meta,
+            ConversionMeta {
+                input_size: (72, 72),
+                output_size: (72, 72),
+                resized: false,
+                mode: ImageMode::BMP,
+                transform: image_format.transform,
+                encoded_len: converted.bytes.len(),
+            }
+        );
+        assert!(validate_image_data(ImageMode::BMP, &converted.bytes).is_ok());
+    }
+
+    #[test]
+    fn meta_of_image_without_encoding() {
+        let image_format = ImageFormat {
+            mode: ImageMode::None,
+            ..ImageFormat::jpeg((60, 60))
+        };
+
+        let converted = convert(image_format, (60, 60));
+
+        assert!(!converted.meta.resized);
+        assert_eq!(converted.meta.encoded_len, 0);
+        assert!(converted.bytes.is_empty());
+    }
+}
@@ -0,0 +1,254 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+use crate::{
+    error::MirajazzError,
+    types::{Geometry, GridPosition, ProtocolVersion, UploadCommand},
+};
+
+/// Description of a known device model
+///
+/// Library doesn't require devices to be known, this is only used for catching configuration mistakes
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Kind {
+    /// Human-readable name of the model
+    pub name: &'static str,
+    /// Model identifier reported by the firmware, for telling apart models sharing a PID
+    pub model: Option<&'static str>,
+    /// Vendor ID of the device
+    pub vendor_id: u16,
+    /// Product ID of the device
+    pub product_id: u16,
+    /// Protocol version of the device
+    pub protocol_version: ProtocolVersion,
+    /// Number of keys
+    pub key_count: usize,
+    /// Number of encoders
+    pub encoder_count: usize,
+    /// Native resolution of key images, after rotation, [None] if keys have different resolutions
+    pub image_size: Option<(usize, usize)>,
+    /// Physical arrangement of keys and encoders, if known
+    pub geometry: Option<Geometry>,
+    /// Features the firmware supports
+    pub capabilities: Capabilities,
+}
+
+/// Features supported by the firmware, for hiding controls that won't work
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Capabilities {
+    /// Display brightness can be set
+    pub brightness: bool,
+    /// Device has a secondary screen besides the key displays
+    pub secondary_screen: bool,
+    /// Device has encoders
+    pub encoders: bool,
+    /// Displayed images stay after the device wakes up from sleep
+    pub images_persist_on_sleep: bool,
+    /// Image shown while booting or sleeping can be uploaded
+    pub boot_logo: bool,
+}
+
+impl Capabilities {
+    /// Everything is assumed to be supported, used for devices that aren't known
+    pub const ALL: Capabilities = Capabilities {
+        brightness: true,
+        secondary_screen: true,
+        encoders: true,
+        images_persist_on_sleep: true,
+        boot_logo: true,
+    };
+
+    /// Returns [MirajazzError::UnsupportedOperation] if display brightness can't be set
+    pub fn require_brightness(&self) -> Result<(), MirajazzError> {
+        if self.brightness {
+            Ok(())
+        } else {
+            Err(MirajazzError::UnsupportedOperation)
+        }
+    }
+
+    /// Returns the command for uploading images to flash, [MirajazzError::UnsupportedOperation]
+    /// if there's none or firmware can't upload the boot logo
+    pub fn require_upload(
+        &self,
+        command: Option<UploadCommand>,
+    ) -> Result<UploadCommand, MirajazzError> {
+        match command {
+            Some(command) if self.boot_logo => Ok(command),
+            _ => Err(MirajazzError::UnsupportedOperation),
+        }
+    }
+}
+
+/// Models known to the library, built on first use since geometries own their encoder positions
+pub static KNOWN_KINDS: LazyLock<Vec<Kind>> = LazyLock::new(|| {
+    vec![
+        Kind {
+            name: "Ajazz AKP153R",
+            model: None,
+            vendor_id: 0x0300,
+            product_id: 0x1020,
+            protocol_version: ProtocolVersion::V1,
+            key_count: 18,
+            encoder_count: 0,
+            image_size: Some((85, 85)),
+            geometry: Some(Geometry {
+                key_size: (85, 85),
+                rows: 3,
+                columns: 6,
+                gap: None,
+                strip: None,
+                encoders: vec![],
+            }),
+            capabilities: Capabilities {
+                brightness: true,
+                secondary_screen: false,
+                encoders: false,
+                images_persist_on_sleep: true,
+                boot_logo: true,
+            },
+        },
+        Kind {
+            name: "Ajazz AKP03R",
+            model: None,
+            vendor_id: 0x0300,
+            product_id: 0x1003,
+            protocol_version: ProtocolVersion::V2,
+            key_count: 9,
+            encoder_count: 3,
+            image_size: Some((60, 60)),
+            geometry: Some(Geometry {
+                key_size: (60, 60),
+                rows: 3,
+                columns: 3,
+                gap: None,
+                strip: None,
+                encoders: vec![
+                    GridPosition { row: 3, column: 0 },
+                    GridPosition { row: 3, column: 1 },
+                    GridPosition { row: 3, column: 2 },
+                ],
+            }),
+            capabilities: Capabilities {
+                brightness: true,
+                secondary_screen: false,
+                encoders: true,
+                images_persist_on_sleep: true,
+                boot_logo: true,
+            },
+        },
+        Kind {
+            name: "Mirabox N1",
+            model: None,
+            vendor_id: 0x6603,
+            product_id: 0x1000,
+            protocol_version: ProtocolVersion::V3,
+            key_count: 18,
+            encoder_count: 0,
+            // Main grid and the top row have different resolutions
+            image_size: None,
+            geometry: None,
+            capabilities: Capabilities {
+                brightness: true,
+                // Top row is a separate screen
+                secondary_screen: true,
+                encoders: false,
+                images_persist_on_sleep: true,
+                boot_logo: true,
+            },
+        },
+    ]
+});
+
+impl Kind {
+    /// Returns physical arrangement of keys and encoders, if known
+    pub fn geometry(&self) -> Option<&Geometry> {
+        self.geometry.as_ref()
+    }
+
+    /// Finds known model by VID and PID
+    pub fn from_vid_pid(vendor_id: u16, product_id: u16) -> Option<&'static Kind> {
+        KNOWN_KINDS
+            .iter()
+            .find(|kind| kind.vendor_id == vendor_id && kind.product_id == product_id)
+    }
+
+    /// Finds known model by VID and PID, using model reported by the firmware as a tiebreaker
+    ///
+    /// Falls back to the model without identifier if none matched
+    pub fn from_vid_pid_model(
+        vendor_id: u16,
+        product_id: u16,
+        model: Option<&str>,
+    ) -> Option<&'static Kind> {
+        let mut candidates = KNOWN_KINDS
+            .iter()
+            .filter(|kind| kind.vendor_id == vendor_id && kind.product_id == product_id);
+
+        let by_model = candidates
+            .clone()
+            .find(|kind| model.is_some() && kind.model == model);
+
+        by_model.or_else(|| candidates.find(|kind| kind.model.is_none()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPLOAD: UploadCommand = UploadCommand {
+        opcode: [0x4c, 0x4f, 0x47],
+        target: 0x02,
+    };
+
+    #[test]
+    fn brightness_is_gated() {
+        let without = Capabilities {
+            brightness: false,
+            ..Capabilities::ALL
+        };
+
+        assert!(Capabilities::ALL.require_brightness().is_ok());
+        assert!(matches!(
+            without.require_brightness(),
+            Err(MirajazzError::UnsupportedOperation)
+        ));
+    }
+
+    #[test]
+    fn upload_is_gated() {
+        let without = Capabilities {
+            boot_logo: false,
+            ..Capabilities::ALL
+        };
+
+        assert_eq!(
+            Capabilities::ALL.require_upload(Some(UPLOAD)).unwrap(),
+            UPLOAD
+        );
+        assert!(matches!(
+            Capabilities::ALL.require_upload(None),
+            Err(MirajazzError::UnsupportedOperation)
+        ));
+        assert!(matches!(
+            without.require_upload(Some(UPLOAD)),
+            Err(MirajazzError::UnsupportedOperation)
+        ));
+    }
+
+    #[test]
+    fn known_geometries_match_counts() {
+        for kind in KNOWN_KINDS.iter() {
+            if let Some(geometry) = kind.geometry() {
+                assert!(geometry
+                    .validate(kind.key_count, kind.encoder_count)
+                    .is_ok());
+            }
+
+            assert_eq!(kind.capabilities.encoders, kind.encoder_count > 0);
+        }
+    }
+}
@@ -1,5 +1,14 @@
+pub mod conformance;
 pub mod device;
 pub mod error;
 pub mod images;
+pub mod kind;
+pub mod manager;
+pub mod probe;
+pub mod protocol;
+pub mod retry;
 pub mod state;
+pub mod stats;
+pub mod testing;
+pub mod throttle;
 pub mod types;
@@ -0,0 +1,445 @@
+use std::{future::Future, panic::resume_unwind, sync::Arc, time::Duration};
+use tokio::{
+    sync::Semaphore,
+    task::JoinSet,
+    time::{self, Instant},
+};
+
+use crate::{
+    device::{normalize_serial, Device, DeviceSnapshot},
+    error::MirajazzError,
+    retry::{self, RetryPolicy},
+    types::{DeviceId, HidDeviceId, HidDeviceInfo},
+};
+
+/// Result of an operation for every device, in the same order as [DeviceManager::devices]
+pub type BulkResult<T> = Vec<(DeviceId, Result<T, MirajazzError>)>;
+
+/// Outcome of reapplying state to a reconnected device
+#[derive(Debug)]
+pub enum ManagerEvent {
+    /// State of the previous connection was reapplied
+    Restored { device_id: DeviceId, took: Duration },
+    /// State couldn't be reapplied before the deadline, error is the last one
+    RestoreFailed {
+        device_id: DeviceId,
+        error: MirajazzError,
+    },
+}
+
+/// Device removed with [DeviceManager::disconnected], kept until it reconnects
+struct DisconnectedDevice {
+    id: DeviceId,
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+    hid_id: HidDeviceId,
+    snapshot: DeviceSnapshot,
+}
+
+/// Collection of connected devices, for running operations on all of them at once
+pub struct DeviceManager {
+    devices: Vec<Arc<Device>>,
+    disconnected: Vec<DisconnectedDevice>,
+    concurrency: usize,
+    fuzzy_reconnect: bool,
+    restore_deadline: Duration,
+    operation_timeout: Duration,
+}
+
+impl Default for DeviceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceManager {
+    /// Builds new empty device manager
+    pub fn new() -> Self {
+        Self {
+            devices: vec![],
+            disconnected: vec![],
+            concurrency: 4,
+            fuzzy_reconnect: false,
+            restore_deadline: Duration::from_secs(5),
+            operation_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Sets how long bulk operations wait for a single device before giving up on it
+    pub fn with_operation_timeout(mut self, timeout: Duration) -> Self {
+        self.operation_timeout = timeout;
+        self
+    }
+
+    /// Sets how long [DeviceManager::reconnect] keeps trying to reapply state of the device
+    pub fn with_restore_deadline(mut self, deadline: Duration) -> Self {
+        self.restore_deadline = deadline;
+        self
+    }
+
+    /// Sets how many devices can be processed by bulk operations at the same time
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Allows [DeviceManager::find_reconnect_candidate] to match devices by platform path
+    /// when serial number doesn't match, see [crate::device::is_reconnect_candidate]
+    pub fn with_fuzzy_reconnect(mut self, fuzzy: bool) -> Self {
+        self.fuzzy_reconnect = fuzzy;
+        self
+    }
+
+    /// Adds device to the manager, replacing device with the same id
+    pub fn add(&mut self, device: Device) -> Arc<Device> {
+        let device = Arc::new(device);
+
+        match self.devices.iter_mut().find(|d| d.id() == device.id()) {
+            Some(existing) => *existing = device.clone(),
+            None => self.devices.push(device.clone()),
+        }
+
+        device
+    }
+
+    /// Removes disconnected device from the manager, keeping its state for [DeviceManager::reconnect]
+    ///
+    /// Device is closed once other clones of it are dropped too, only then it can be connected again
+    /// on the same platform path. Returns `false` if device wasn't managed
+    pub async fn disconnected(&mut self, id: &DeviceId) -> bool {
+        let device = match self.remove(id) {
+            Some(device) => device,
+            None => return false,
+        };
+
+        let snapshot = device.snapshot().await;
+
+        self.disconnected
+            .retain(|disconnected| &disconnected.id != id);
+        self.disconnected.push(DisconnectedDevice {
+            id: id.clone(),
+            vid: device.vid,
+            pid: device.pid,
+            serial_number: device.serial_number().map(str::to_string),
+            hid_id: device.hid_id().clone(),
+            snapshot,
+        });
+
+        true
+    }
+
+    /// Adds reconnected device to the manager, reapplying brightness, orientation and images
+    /// of the device it replaces
+    ///
+    /// Replaced device is looked up by id, or matched the same way as
+    /// [DeviceManager::find_reconnect_candidate], first among devices removed with
+    /// [DeviceManager::disconnected], then among managed ones. Failed attempts are retried
+    /// with backoff until the deadline set by [DeviceManager::with_restore_deadline].
+    /// Returns [None] if device wasn't managed before
+    pub async fn reconnect(
+        &mut self,
+        info: &HidDeviceInfo,
+        device: Device,
+    ) -> Option<ManagerEvent> {
+        let snapshot = match self.take_disconnected(info, device.id()) {
+            Some(snapshot) => snapshot,
+            None => {
+                let previous = match self.get(device.id()) {
+                    Some(previous) => Some(previous),
+                    None => self.find_reconnect_candidate(info),
+                };
+
+                let previous = match previous {
+                    Some(previous) => previous,
+                    None => {
+                        self.add(device);
+                        return None;
+                    }
+                };
+
+                let snapshot = previous.snapshot().await;
+
+                self.remove(previous.id());
+
+                snapshot
+            }
+        };
+
+        let device = self.add(device);
+        let device_id = device.id().clone();
+
+        let policy = RetryPolicy {
+            max_attempts: usize::MAX,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::MAX,
+            deadline: Some(self.restore_deadline),
+            is_retryable: |_| true,
+            ..RetryPolicy::default()
+        };
+
+        let started = Instant::now();
+
+        match retry::with_policy(&policy, || device.restore(&snapshot)).await {
+            Ok(()) => Some(ManagerEvent::Restored {
+                device_id,
+                took: started.elapsed(),
+            }),
+            Err(error) => Some(ManagerEvent::RestoreFailed { device_id, error }),
+        }
+    }
+
+    /// Removes device from the manager
+    pub fn remove(&mut self, id: &DeviceId) -> Option<Arc<Device>> {
+        let index = self.devices.iter().position(|d| d.id() == id)?;
+
+        Some(self.devices.remove(index))
+    }
+
+    /// Returns device with provided id
+    pub fn get(&self, id: &DeviceId) -> Option<Arc<Device>> {
+        self.devices.iter().find(|d| d.id() == id).cloned()
+    }
+
+    /// Returns managed device that the newly appeared device is a reconnection of
+    ///
+    /// Devices are matched by vid/pid and serial number, and with fuzzy reconnect enabled,
+    /// by vid/pid and platform path if no serial number matched. Devices without serial number
+    /// are always matched by platform path
+    pub fn find_reconnect_candidate(&self, info: &HidDeviceInfo) -> Option<Arc<Device>> {
+        let candidates = self.devices.iter().map(|device| {
+            (
+                device.vid,
+                device.pid,
+                device.serial_number(),
+                device.hid_id(),
+            )
+        });
+
+        let index = self.reconnect_candidate(info, candidates)?;
+
+        Some(self.devices[index].clone())
+    }
+
+    /// Takes state of the disconnected device that the newly appeared device is a reconnection of
+    fn take_disconnected(&mut self, info: &HidDeviceInfo, id: &DeviceId) -> Option<DeviceSnapshot> {
+        let index = match self.disconnected.iter().position(|d| &d.id == id) {
+            Some(index) => index,
+            None => {
+                let candidates = self.disconnected.iter().map(|device| {
+                    let serial = device.serial_number.as_deref();
+
+                    (device.vid, device.pid, serial, &device.hid_id)
+                });
+
+                self.reconnect_candidate(info, candidates)?
+            }
+        };
+
+        Some(self.disconnected.remove(index).snapshot)
+    }
+
+    /// Returns index of the candidate the device is a reconnection of,
+    /// candidates are described by vid, pid, normalized serial number and platform path
+    fn reconnect_candidate<'a>(
+        &self,
+        info: &HidDeviceInfo,
+        candidates: impl Iterator<Item = (u16, u16, Option<&'a str>, &'a HidDeviceId)> + Clone,
+    ) -> Option<usize> {
+        let serial = info
+            .serial_number
+            .as_deref()
+            .map(normalize_serial)
+            .filter(|serial| !serial.is_empty());
+
+        let same_model =
+            |(_, (vid, pid, _, _)): &(usize, (u16, u16, Option<&str>, &HidDeviceId))| {
+                *vid == info.vendor_id && *pid == info.product_id
+            };
+
+        let by_serial = candidates
+            .clone()
+            .enumerate()
+            .filter(same_model)
+            .find(|(_, (_, _, candidate, _))| serial.is_some() && serial.as_deref() == *candidate);
+
+        match by_serial {
+            Some((index, _)) => Some(index),
+            None if self.fuzzy_reconnect || serial.is_none() => candidates
+                .enumerate()
+                .filter(same_model)
+                .find(|(_, (_, _, _, hid_id))| *hid_id == &info.id)
+                .map(|(index, _)| index),
+            None => None,
+        }
+    }
+
+    /// Returns managed devices, in the order they were added
+    pub fn devices(&self) -> &[Arc<Device>] {
+        &self.devices
+    }
+
+    /// Runs the operation on every device concurrently
+    ///
+    /// Failure of one device doesn't stop the others, every device gets its own result.
+    /// Device that doesn't finish within [DeviceManager::with_operation_timeout] gets
+    /// [MirajazzError::OperationTimeout], waiting for a free slot doesn't count towards it.
+    /// Results are in the same order as [DeviceManager::devices], regardless of completion order
+    pub async fn for_each<F, Fut, T>(&self, operation: F) -> BulkResult<T>
+    where
+        F: Fn(Arc<Device>) -> Fut,
+        Fut: Future<Output = Result<T, MirajazzError>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let results = run_bulk(
+            &self.devices,
+            self.concurrency,
+            self.operation_timeout,
+            operation,
+        )
+        .await;
+
+        self.devices
+            .iter()
+            .zip(results)
+            .map(|(device, result)| (device.id().clone(), result))
+            .collect()
+    }
+
+    /// Sets brightness of every device, value range is 0 - 100
+    pub async fn set_brightness_all(&self, percent: u8) -> BulkResult<()> {
+        self.for_each(|device| async move { device.set_brightness(percent).await })
+            .await
+    }
+
+    /// Clears images of every device
+    pub async fn clear_all(&self) -> BulkResult<()> {
+        self.for_each(|device| async move { device.clear_all_button_images().await })
+            .await
+    }
+
+    /// Flushes written images of every device
+    pub async fn flush_all(&self) -> BulkResult<()> {
+        self.for_each(|device| async move { device.flush().await })
+            .await
+    }
+}
+
+/// Runs the operation for every item with bounded concurrency and a timeout per item,
+/// results are in the order of items
+async fn run_bulk<I, F, Fut, T>(
+    items: &[I],
+    concurrency: usize,
+    timeout: Duration,
+    operation: F,
+) -> Vec<Result<T, MirajazzError>>
+where
+    I: Clone,
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = Result<T, MirajazzError>> + Send + 'static,
+    T: Send + 'static,
+{
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = JoinSet::new();
+
+    for (index, item) in items.iter().enumerate() {
+        let semaphore = semaphore.clone();
+        let operation = operation(item.clone());
+
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+
+            let result = match time::timeout(timeout, operation).await {
+                Ok(result) => result,
+                Err(_) => Err(MirajazzError::OperationTimeout),
+            };
+
+            (index, result)
+        });
+    }
+
+    let mut results = Vec::with_capacity(items.len());
+
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(result) => results.push(result),
+            Err(e) => resume_unwind(e.into_panic()),
+        }
+    }
+
+    results.sort_by_key(|(index, _)| *index);
+
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Healthy devices answer after their delay, failing ones with an error, dead ones never
+    #[derive(Clone, Copy)]
+    enum MockDevice {
+        Healthy(u64),
+        Failing,
+        Dead,
+    }
+
+    async fn operate(device: MockDevice) -> Result<u64, MirajazzError> {
+        match device {
+            MockDevice::Healthy(delay) => {
+                time::sleep(Duration::from_millis(delay)).await;
+
+                Ok(delay)
+            }
+            MockDevice::Failing => Err(MirajazzError::BadData),
+            MockDevice::Dead => std::future::pending().await,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn results_follow_device_order() {
+        let devices = [
+            MockDevice::Healthy(300),
+            MockDevice::Failing,
+            MockDevice::Healthy(100),
+            MockDevice::Healthy(200),
+        ];
+
+        let results = run_bulk(&devices, 4, Duration::from_secs(1), operate).await;
+
+        assert!(matches!(results[0], Ok(300)));
+        assert!(matches!(results[1], Err(MirajazzError::BadData)));
+        assert!(matches!(results[2], Ok(100)));
+        assert!(matches!(results[3], Ok(200)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dead_device_times_out_without_stalling_others() {
+        let devices = [
+            MockDevice::Dead,
+            MockDevice::Healthy(100),
+            MockDevice::Failing,
+            MockDevice::Healthy(100),
+        ];
+        let started = Instant::now();
+
+        let results = run_bulk(&devices, 2, Duration::from_secs(1), operate).await;
+
+        assert!(matches!(results[0], Err(MirajazzError::OperationTimeout)));
+        assert!(matches!(results[1], Ok(100)));
+        assert!(matches!(results[2], Err(MirajazzError::BadData)));
+        assert!(matches!(results[3], Ok(100)));
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrency_is_bounded() {
+        let devices = [MockDevice::Healthy(100); 4];
+        let started = Instant::now();
+
+        let results = run_bulk(&devices, 2, Duration::from_secs(1), operate).await;
+
+        assert!(results.iter().all(|result| matches!(result, Ok(100))));
+        assert_eq!(started.elapsed(), Duration::from_millis(200));
+    }
+}
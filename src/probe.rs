@@ -0,0 +1,174 @@
+use async_hid::{AsyncHidRead, HidBackend};
+use futures_lite::FutureExt;
+use std::{fmt::Write, time::Duration};
+use tokio::time::{self, Instant};
+
+use crate::{
+    device::Device,
+    error::MirajazzError,
+    kind::Kind,
+    protocol::{classify_notification, parse_model},
+    types::{DeviceNotification, HidDeviceInfo},
+};
+
+/// Size of the buffer used for reading input reports of unknown devices, fits every protocol version
+const PROBE_REPORT_SIZE: usize = 1024;
+
+/// Information gathered about the device without sending any commands to it
+#[derive(Clone, Debug)]
+pub struct ProbeReport {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub usage_page: u16,
+    pub usage_id: u16,
+    pub name: String,
+    pub manufacturer: Option<String>,
+    pub serial_number: Option<String>,
+    /// Firmware version report, as returned by the device
+    pub firmware_report: Option<Vec<u8>>,
+    /// Model reported by the firmware, if present
+    pub model: Option<String>,
+    /// Known model matching the device, if any
+    pub kind: Option<&'static Kind>,
+    /// Input reports received while listening, with time since listening started
+    pub input_reports: Vec<(Duration, Vec<u8>)>,
+}
+
+/// Gathers information about possibly unsupported device, only reading from it
+///
+/// Device is opened, firmware version is queried and input reports are collected for `listen` duration,
+/// so the user can press keys and twist encoders meanwhile. Nothing is written to the device,
+/// so the screen and settings are left untouched
+pub async fn probe(info: &HidDeviceInfo, listen: Duration) -> Result<ProbeReport, MirajazzError> {
+    let device = HidBackend::default().query_devices(&info.id).await?.last();
+
+    let device = match device {
+        Some(device) => device,
+        None => return Err(MirajazzError::DeviceNotFoundError),
+    };
+
+    let firmware_report = Device::read_firmware_report(&device).await?;
+    let model = firmware_report.as_deref().and_then(parse_model);
+    let kind = Kind::from_vid_pid_model(device.vendor_id, device.product_id, model.as_deref());
+
+    let (mut reader, _writer) = device.open().await?;
+
+    let started = Instant::now();
+    let deadline = started + listen;
+    let mut input_reports = vec![];
+
+    while Instant::now() < deadline {
+        let mut buf = vec![0u8; PROBE_REPORT_SIZE];
+
+        let size = reader
+            .read_input_report(&mut buf)
+            .or(async {
+                time::sleep_until(deadline).await;
+                Ok(0)
+            })
+            .await?;
+
+        if size == 0 {
+            break;
+        }
+
+        buf.truncate(size);
+        input_reports.push((started.elapsed(), buf));
+    }
+
+    Ok(ProbeReport {
+        vendor_id: info.vendor_id,
+        product_id: info.product_id,
+        usage_page: info.usage_page,
+        usage_id: info.usage_id,
+        name: info.name.clone(),
+        manufacturer: info.manufacturer.clone(),
+        serial_number: info.serial_number.clone(),
+        firmware_report,
+        model,
+        kind,
+        input_reports,
+    })
+}
+
+impl ProbeReport {
+    /// Returns the largest input report received, best guess for the input report size
+    pub fn input_report_size(&self) -> Option<usize> {
+        self.input_reports
+            .iter()
+            .map(|(_, report)| report.len())
+            .max()
+    }
+
+    /// Returns distinct key indices seen in input reports, at the place where known devices report them
+    pub fn seen_keys(&self) -> Vec<u8> {
+        let mut keys: Vec<u8> = self
+            .input_reports
+            .iter()
+            .filter(|(_, report)| report.starts_with(&[65, 67, 75]))
+            .filter_map(|(_, report)| report.get(9).copied())
+            .filter(|key| *key != 0)
+            .collect();
+
+        keys.sort_unstable();
+        keys.dedup();
+
+        keys
+    }
+
+    /// Returns status notifications device sent while listening
+    pub fn notifications(&self) -> Vec<DeviceNotification> {
+        self.input_reports
+            .iter()
+            .filter_map(|(_, report)| classify_notification(report))
+            .collect()
+    }
+
+    /// Returns skeleton of the device profile in TOML, filled with best guesses
+    ///
+    /// Fields that can't be guessed are left commented out
+    pub fn profile_skeleton(&self) -> String {
+        let mut out = String::new();
+
+        // Writing to String can't fail
+        let _ = writeln!(out, "# Generated by mirajazz probe");
+        let _ = writeln!(out, "name = {:?}", self.name);
+        let _ = writeln!(out, "vendor_id = 0x{:04X}", self.vendor_id);
+        let _ = writeln!(out, "product_id = 0x{:04X}", self.product_id);
+        let _ = writeln!(out, "usage_page = {}", self.usage_page);
+        let _ = writeln!(out, "usage_id = {}", self.usage_id);
+
+        if let Some(model) = &self.model {
+            let _ = writeln!(out, "model = {:?}", model);
+        }
+
+        match self.kind {
+            Some(kind) => {
+                let _ = writeln!(out, "# Matches known model {}", kind.name);
+                let _ = writeln!(out, "protocol_version = {:?}", kind.protocol_version);
+                let _ = writeln!(out, "key_count = {}", kind.key_count);
+                let _ = writeln!(out, "encoder_count = {}", kind.encoder_count);
+            }
+            None => {
+                let _ = writeln!(out, "# protocol_version = ?");
+
+                match self.seen_keys().last() {
+                    Some(key) => {
+                        let _ = writeln!(out, "# key_count = {} (highest key index seen)", key);
+                    }
+                    None => {
+                        let _ = writeln!(out, "# key_count = ?");
+                    }
+                }
+
+                let _ = writeln!(out, "# encoder_count = ?");
+            }
+        }
+
+        if let Some(size) = self.input_report_size() {
+            let _ = writeln!(out, "input_report_size = {}", size);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,675 @@
+use crate::{
+    error::MirajazzError,
+    types::{DeviceNotification, UploadCommand},
+};
+
+/// Prefix of commands, also used by the device for status notifications
+pub const CRT_PREFIX: [u8; 5] = [0x43, 0x52, 0x54, 0x00, 0x00];
+
+/// Wakes the display up, no parameters, no response
+///
+/// Also sent by the device as a notification after waking up
+pub const CMD_DIS: [u8; 3] = *b"DIS";
+
+/// Sets display brightness, followed by two zero bytes and percent (0 - 100), no response
+pub const CMD_LIG: [u8; 3] = *b"LIG";
+
+/// Sets brightness of the knob LEDs, followed by percent (0 - 100), no response
+pub const CMD_LBLIG: [u8; 5] = *b"LBLIG";
+
+/// Sets colors of the knob LEDs, followed by `[r, g, b]` for every LED, no response
+pub const CMD_SETLB: [u8; 5] = *b"SETLB";
+
+/// Starts image transfer, followed by big-endian u16 checksum or two zero bytes,
+/// big-endian u16 image length and key index + 1
+///
+/// Image data is sent in the following reports. Firmwares that validate the checksum respond
+/// with acknowledgment, or [NAK] when it doesn't match, others don't respond
+pub const CMD_BAT: [u8; 3] = *b"BAT";
+
+/// Clears the screen, followed by three zero bytes and key index + 1, or `0xff` for every key
+///
+/// Followed by two zero bytes and `DC` instead, shows the standby screen before disconnecting
+pub const CMD_CLE: [u8; 3] = *b"CLE";
+
+/// Commits sent images and clears to the screen, no parameters, no response
+pub const CMD_STP: [u8; 3] = *b"STP";
+
+/// Puts the device to sleep, no parameters, no response
+///
+/// Also sent by the device as a notification after going to sleep
+pub const CMD_HAN: [u8; 3] = *b"HAN";
+
+/// Keeps the connection alive, no parameters, no response
+pub const CMD_CON: [u8; 7] = *b"CONNECT";
+
+/// Switches device mode, followed by two zero bytes and ASCII digit of the mode, no response
+pub const CMD_MOD: [u8; 3] = *b"MOD";
+
+/// Sent by the device when rendering fails, followed by error code and key index + 1, or zero if unknown
+///
+/// Previous image is shown on the wrong key until the key is sent again
+pub const CMD_ERR: [u8; 3] = *b"ERR";
+
+/// Prefix of the response rejecting image transfer because of checksum mismatch,
+/// key index + 1 is at the same place as in input reports
+pub const NAK: [u8; 3] = *b"NAK";
+
+/// Known opcodes of status notifications
+const NOTIFICATIONS: &[([u8; 3], DeviceNotification)] = &[
+    (CMD_HAN, DeviceNotification::SleepEntered),
+    (CMD_DIS, DeviceNotification::WokeUp),
+    (*b"HOT", DeviceNotification::Overheat),
+];
+
+/// Command understood by the device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// Wakes the display up
+    Wake,
+    /// Sets display brightness, value range is 0 - 100
+    Brightness(u8),
+    /// Sets brightness of the knob LEDs, value range is 0 - 100
+    LedBrightness(u8),
+    /// Sets colors of the knob LEDs
+    LedColors(&'a [[u8; 3]]),
+    /// Starts transfer of image data of specified length to the key, checksum is zero if disabled
+    ImageHeader {
+        key: u8,
+        length: usize,
+        checksum: u16,
+    },
+    /// Requests image of the key using device specific opcode
+    ReadImage { opcode: [u8; 3], key: u8 },
+    /// Requests firmware version using device specific opcode, see [is_version_reply]
+    QueryVersion { opcode: [u8; 3] },
+    /// Starts transfer of image data of specified length to the flash slot, zero length resets the slot
+    Upload {
+        command: UploadCommand,
+        length: usize,
+    },
+    /// Clears the image of the key
+    ClearKey(u8),
+    /// Clears images of every key
+    ClearAll,
+    /// Commits sent images and clears to the screen
+    Commit,
+    /// Puts the device to sleep
+    Sleep,
+    /// Keeps the connection alive
+    KeepAlive,
+    /// Clears the screen and shows the standby screen, used before disconnecting and for turning display off
+    Shutdown,
+    /// Switches device mode
+    Mode(u8),
+}
+
+impl Command<'_> {
+    /// Encodes the command into output report payload, including leading report id
+    ///
+    /// Payload is not padded, see [crate::device::Device::write_extended_data]
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![0x00];
+        buf.extend_from_slice(&CRT_PREFIX);
+
+        match self {
+            Command::Wake => buf.extend_from_slice(&CMD_DIS),
+            Command::Brightness(percent) => {
+                buf.extend_from_slice(&CMD_LIG);
+                buf.extend_from_slice(&[0x00, 0x00, *percent]);
+            }
+            Command::LedBrightness(percent) => {
+                buf.extend_from_slice(&CMD_LBLIG);
+                buf.push(*percent);
+            }
+            Command::LedColors(colors) => {
+                buf.extend_from_slice(&CMD_SETLB);
+                buf.extend(colors.iter().flatten());
+            }
+            Command::ImageHeader {
+                key,
+                length,
+                checksum,
+            } => {
+                buf.extend_from_slice(&CMD_BAT);
+                buf.extend_from_slice(&checksum.to_be_bytes());
+                buf.extend_from_slice(&[(length >> 8) as u8, *length as u8, key + 1]);
+            }
+            Command::ReadImage { opcode, key } => {
+                buf.extend_from_slice(opcode);
+                buf.extend_from_slice(&[0x00, 0x00, 0x00, key.wrapping_add(1)]);
+            }
+            Command::QueryVersion { opcode } => buf.extend_from_slice(opcode),
+            Command::Upload { command, length } => {
+                buf.extend_from_slice(&command.opcode);
+                buf.extend_from_slice(&[
+                    0x00,
+                    0x00,
+                    (length >> 8) as u8,
+                    *length as u8,
+                    command.target,
+                ]);
+            }
+            Command::ClearKey(key) => {
+                buf.extend_from_slice(&CMD_CLE);
+                // Key 0xff clears every key, same as [Command::ClearAll]
+                buf.extend_from_slice(&[0x00, 0x00, 0x00, key.checked_add(1).unwrap_or(0xff)]);
+            }
+            Command::ClearAll => {
+                buf.extend_from_slice(&CMD_CLE);
+                buf.extend_from_slice(&[0x00, 0x00, 0x00, 0xff]);
+            }
+            Command::Commit => buf.extend_from_slice(&CMD_STP),
+            Command::Sleep => buf.extend_from_slice(&CMD_HAN),
+            Command::KeepAlive => buf.extend_from_slice(&CMD_CON),
+            Command::Shutdown => {
+                buf.extend_from_slice(&CMD_CLE);
+                buf.extend_from_slice(&[0x00, 0x00, 0x44, 0x43]);
+            }
+            Command::Mode(mode) => {
+                buf.extend_from_slice(&CMD_MOD);
+                buf.extend_from_slice(&[0x00, 0x00, 0x30 + mode]);
+            }
+        }
+
+        buf
+    }
+}
+
+/// Encoding of commands, for firmware variants whose command set differs from the usual one,
+/// see [crate::device::Device::with_protocol]
+///
+/// Every command the device sends goes through [DeviceProtocol::encode], including the wake-up
+/// and brightness sent while initializing, so implementations only handle the commands that differ
+/// and pass the rest to [Command::encode]
+pub trait DeviceProtocol: Send + Sync {
+    /// Encodes the command into output report payload, including leading report id
+    fn encode(&self, command: &Command<'_>) -> Vec<u8> {
+        command.encode()
+    }
+
+    /// How image data is framed
+    fn image_framing(&self) -> ImageFraming {
+        ImageFraming::HeaderCommand
+    }
+}
+
+/// How image data is split into packets
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum ImageFraming {
+    /// [Command::ImageHeader] is sent first, followed by packets of raw image data
+    #[default]
+    HeaderCommand,
+    /// Every packet starts with its own header, see [legacy_packet_header]
+    PerPacketHeader,
+}
+
+/// Header of image data packet for [ImageFraming::PerPacketHeader], without report id:
+/// `0x01`, key index + 1, page number, last page flag and little-endian u16 length of the data
+pub fn legacy_packet_header(key: u8, page: usize, is_last: bool, length: usize) -> [u8; 6] {
+    [
+        0x01,
+        key + 1,
+        page as u8,
+        is_last as u8,
+        length as u8,
+        (length >> 8) as u8,
+    ]
+}
+
+/// Command set of Mirabox and Ajazz devices, used by default
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MiraboxProtocol;
+
+impl DeviceProtocol for MiraboxProtocol {}
+
+/// Command set of older AKP153E firmwares, that frame every image packet with its own header
+/// and use single-byte opcodes for clearing and brightness
+///
+/// Other commands are sent as usual
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Legacy153Protocol;
+
+impl DeviceProtocol for Legacy153Protocol {
+    fn encode(&self, command: &Command<'_>) -> Vec<u8> {
+        match command {
+            Command::Brightness(percent) => vec![0x00, 0x03, *percent],
+            Command::ClearKey(key) => vec![0x00, 0x02, key + 1],
+            Command::ClearAll => vec![0x00, 0x02, 0xff],
+            command => command.encode(),
+        }
+    }
+
+    fn image_framing(&self) -> ImageFraming {
+        ImageFraming::PerPacketHeader
+    }
+}
+
+/// Classifies input report as a status notification
+///
+/// Notifications are prefixed the same way as commands, followed by the opcode.
+/// Unknown opcodes are returned as [DeviceNotification::Raw]
+pub fn classify_notification(data: &[u8]) -> Option<DeviceNotification> {
+    if data.len() < 8 || !data.starts_with(&CRT_PREFIX) {
+        return None;
+    }
+
+    let opcode = [data[5], data[6], data[7]];
+
+    if let Some((code, key)) = parse_render_error(data) {
+        return Some(DeviceNotification::RenderError { code, key });
+    }
+
+    let notification = NOTIFICATIONS
+        .iter()
+        .find(|(known, _)| *known == opcode)
+        .map(|(_, notification)| *notification)
+        .unwrap_or(DeviceNotification::Raw(opcode));
+
+    Some(notification)
+}
+
+/// Builds the report device sends for the notification, [classify_notification] parses it back
+pub fn encode_notification(notification: DeviceNotification) -> Vec<u8> {
+    let mut report = CRT_PREFIX.to_vec();
+
+    match notification {
+        DeviceNotification::RenderError { code, key } => {
+            report.extend_from_slice(&CMD_ERR);
+            report.extend_from_slice(&[code, key.map_or(0, |key| key.wrapping_add(1))]);
+        }
+        DeviceNotification::Raw(opcode) => report.extend_from_slice(&opcode),
+        notification => {
+            let opcode = NOTIFICATIONS
+                .iter()
+                .find(|(_, known)| *known == notification)
+                .map_or([0x00; 3], |(opcode, _)| *opcode);
+
+            report.extend_from_slice(&opcode);
+        }
+    }
+
+    report
+}
+
+/// Parses render error report, returns error code and the affected key, if device reported it
+pub fn parse_render_error(data: &[u8]) -> Option<(u8, Option<u8>)> {
+    if data.len() < 10 || !data.starts_with(&CRT_PREFIX) || data[5..8] != CMD_ERR {
+        return None;
+    }
+
+    Some((data[8], data[9].checked_sub(1)))
+}
+
+/// Checks if the report carries nothing, some devices send empty or all-zero reports,
+/// or echo the keep-alive (CONNECT) back every few seconds
+pub fn is_noop_report(data: &[u8]) -> bool {
+    data.iter().all(|byte| *byte == 0)
+        || (data.starts_with(&CRT_PREFIX) && data[CRT_PREFIX.len()..].starts_with(&CMD_CON))
+}
+
+/// Checks if the report acknowledges the commit (STP) or checksummed image transfer
+///
+/// Acknowledgment is prefixed with ACK the same way as input reports, but doesn't carry a key index
+pub fn is_commit_ack(data: &[u8]) -> bool {
+    data.starts_with(&[65, 67, 75]) && data.get(9).is_none_or(|key| *key == 0)
+}
+
+/// Checks if the report rejects image transfer to the key because of checksum mismatch
+pub fn is_transfer_nak(data: &[u8], key: u8) -> bool {
+    data.starts_with(&NAK) && data.get(9) == Some(&(key + 1))
+}
+
+/// Wrapping 16-bit sum of the bytes
+///
+/// `sum16(b"123456789") == 0x01dd`
+pub fn sum16(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16))
+}
+
+/// CRC-16/CCITT-FALSE, polynomial 0x1021, initial value 0xffff, no reflection
+///
+/// `crc16(b"123456789") == 0x29b1`
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0xffff, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Extracts model identifier from the firmware version report, like `293`, `153E` or `N4`
+///
+/// Report starts with the report id, followed by NUL-terminated version, and some firmwares
+/// put model identifier after it. Returns [None] if there's no such field
+pub fn parse_model(report: &[u8]) -> Option<String> {
+    let model = report
+        .get(1..)?
+        .split(|byte| *byte == 0x00)
+        .filter(|field| !field.is_empty())
+        .nth(1)?;
+
+    if !model.iter().all(|byte| byte.is_ascii_graphic()) {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(model).to_string())
+}
+
+/// Extracts version string from the firmware version report, the first NUL-terminated field
+/// after the report id
+///
+/// Returns [None] if the field is empty, [MirajazzError::BadData] if it isn't printable ASCII
+pub fn parse_firmware_version(report: &[u8]) -> Result<Option<String>, MirajazzError> {
+    match report.get(1..) {
+        Some(data) => parse_version_string(data),
+        None => Ok(None),
+    }
+}
+
+/// Extends payload starting with report id to a full packet of provided size, plus the report id
+///
+/// Returns [MirajazzError::PayloadTooLarge] if payload doesn't fit into a single packet,
+/// and [MirajazzError::MissingReportId] if it doesn't start with report id, including empty
+/// payloads, which would be sent as a packet of zeroes
+pub fn extend_payload(payload: &[u8], packet_size: usize) -> Result<Vec<u8>, MirajazzError> {
+    if payload.len() > 1 + packet_size {
+        return Err(MirajazzError::PayloadTooLarge {
+            size: payload.len(),
+            max: 1 + packet_size,
+        });
+    }
+
+    if payload.first() != Some(&0x00) {
+        return Err(MirajazzError::MissingReportId);
+    }
+
+    let mut buf = Vec::with_capacity(1 + packet_size);
+
+    buf.extend_from_slice(payload);
+    buf.resize(1 + packet_size, 0);
+
+    Ok(buf)
+}
+
+/// Checks if the input report starts the answer to [Command::ReadImage] with the opcode,
+/// headers echo the opcode after the ACK prefix, followed by the key and image length
+pub fn is_image_readback_header(data: &[u8], opcode: [u8; 3]) -> bool {
+    data.len() >= 12 && data.starts_with(&[65, 67, 75]) && data[5..8] == opcode
+}
+
+/// Checks if the input report is an input or reply of the device rather than chunk of image data
+pub fn is_device_report(data: &[u8]) -> bool {
+    data.starts_with(&[65, 67, 75]) || data.starts_with(&CRT_PREFIX) || data.starts_with(&NAK)
+}
+
+/// Checks if the input report answers [Command::QueryVersion] with the opcode,
+/// replies echo the opcode after the command prefix, followed by the version string
+pub fn is_version_reply(data: &[u8], opcode: [u8; 3]) -> bool {
+    data.starts_with(&CRT_PREFIX) && data.get(5..8) == Some(&opcode[..])
+}
+
+/// Extracts version string from the reply to [Command::QueryVersion], see [parse_firmware_version]
+pub fn parse_version_reply(data: &[u8]) -> Result<Option<String>, MirajazzError> {
+    match data.get(8..) {
+        Some(data) => parse_version_string(data),
+        None => Ok(None),
+    }
+}
+
+/// Parses NUL-terminated printable version string
+fn parse_version_string(data: &[u8]) -> Result<Option<String>, MirajazzError> {
+    let version = data.split(|byte| *byte == 0x00).next().unwrap_or_default();
+    let version = version.trim_ascii();
+
+    if version.is_empty() {
+        return Ok(None);
+    }
+
+    if !version
+        .iter()
+        .all(|byte| byte.is_ascii_graphic() || *byte == b' ')
+    {
+        return Err(MirajazzError::BadData);
+    }
+
+    Ok(Some(String::from_utf8_lossy(version).to_string()))
+}
+
+/// Collects payload of a multi-packet response from the device
+///
+/// Mirror of the way image data is split into output reports, payload is accumulated
+/// until the expected amount of bytes is received, padding of the last report is dropped
+#[derive(Clone, Debug)]
+pub struct ChunkCollector {
+    expected: usize,
+    data: Vec<u8>,
+}
+
+impl ChunkCollector {
+    /// Creates collector expecting specified amount of payload bytes
+    pub fn new(expected: usize) -> Self {
+        Self {
+            expected,
+            data: Vec::with_capacity(expected),
+        }
+    }
+
+    /// Appends payload of a single report, returns true when all expected data was collected
+    pub fn push(&mut self, payload: &[u8]) -> bool {
+        let this_length = self.remaining().min(payload.len());
+
+        self.data.extend(&payload[..this_length]);
+
+        self.is_complete()
+    }
+
+    /// Returns amount of bytes that are still expected
+    pub fn remaining(&self) -> usize {
+        self.expected - self.data.len()
+    }
+
+    /// Checks if all expected data was collected
+    pub fn is_complete(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Returns collected data
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ProtocolVersion;
+
+    #[test]
+    fn notifications_round_trip() {
+        for notification in [
+            DeviceNotification::SleepEntered,
+            DeviceNotification::WokeUp,
+            DeviceNotification::Overheat,
+            DeviceNotification::RenderError {
+                code: 2,
+                key: Some(4),
+            },
+            DeviceNotification::RenderError { code: 1, key: None },
+            DeviceNotification::Raw(*b"XYZ"),
+        ] {
+            let report = encode_notification(notification);
+
+            assert_eq!(classify_notification(&report), Some(notification));
+        }
+    }
+
+    /// Packets as they were built by hand before [Command], padded to packet size
+    fn legacy_packet(bytes: &[u8], packet_size: usize) -> Vec<u8> {
+        let mut buf = bytes.to_vec();
+        buf.resize(1 + packet_size, 0);
+
+        buf
+    }
+
+    fn encode_packet(command: Command<'_>, packet_size: usize) -> Vec<u8> {
+        extend_payload(&command.encode(), packet_size).unwrap()
+    }
+
+    #[test]
+    fn brightness_matches_legacy_packet() {
+        let legacy = [
+            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x4c, 0x49, 0x47, 0x00, 0x00, 50,
+        ];
+
+        assert_eq!(Command::Brightness(50).encode(), legacy.to_vec());
+        assert_eq!(
+            encode_packet(Command::Brightness(50), 512),
+            legacy_packet(&legacy, 512)
+        );
+    }
+
+    #[test]
+    fn clear_key_matches_legacy_packet() {
+        let legacy = [
+            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x43, 0x4c, 0x45, 0x00, 0x00, 0x00, 4,
+        ];
+
+        assert_eq!(Command::ClearKey(3).encode(), legacy.to_vec());
+
+        let legacy_all = [
+            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x43, 0x4c, 0x45, 0x00, 0x00, 0x00, 0xff,
+        ];
+
+        assert_eq!(Command::ClearKey(0xff).encode(), legacy_all.to_vec());
+        assert_eq!(Command::ClearAll.encode(), legacy_all.to_vec());
+    }
+
+    #[test]
+    fn commit_matches_legacy_packet() {
+        let legacy = [0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x53, 0x54, 0x50];
+
+        assert_eq!(Command::Commit.encode(), legacy.to_vec());
+    }
+
+    #[test]
+    fn image_header_matches_legacy_packet() {
+        let length = 0x1234;
+        let legacy = [
+            0x00, 0x43, 0x52, 0x54, 0x00, 0x00, 0x42, 0x41, 0x54, 0x00, 0x00, 0x12, 0x34, 6,
+        ];
+
+        for version in [ProtocolVersion::V1, ProtocolVersion::V2] {
+            let packet_size = version.packet_size();
+            let header = Command::ImageHeader {
+                key: 5,
+                length,
+                checksum: 0,
+            };
+
+            assert_eq!(
+                encode_packet(header, packet_size),
+                legacy_packet(&legacy, packet_size)
+            );
+        }
+    }
+
+    #[test]
+    fn sum16_check_value() {
+        assert_eq!(sum16(b"123456789"), 0x01dd);
+        assert_eq!(sum16(&[]), 0);
+    }
+
+    #[test]
+    fn sum16_wraps_around() {
+        // 258 * 0xff is 0x100fe
+        assert_eq!(sum16(&[0xff; 258]), 0x00fe);
+    }
+
+    #[test]
+    fn crc16_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn extend_payload_pads_to_packet_size() {
+        let buf = extend_payload(&[0x00, 1, 2], 8).unwrap();
+
+        assert_eq!(buf, vec![0x00, 1, 2, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn extend_payload_exact_fit() {
+        let payload = [0x00, 1, 2, 3, 4, 5, 6, 7, 8];
+
+        assert_eq!(extend_payload(&payload, 8).unwrap(), payload.to_vec());
+    }
+
+    #[test]
+    fn extend_payload_one_over() {
+        let payload = [0x00, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+
+        assert!(matches!(
+            extend_payload(&payload, 8),
+            Err(MirajazzError::PayloadTooLarge { size: 10, max: 9 })
+        ));
+    }
+
+    #[test]
+    fn extend_payload_rejects_empty_payload() {
+        assert!(matches!(
+            extend_payload(&[], 8),
+            Err(MirajazzError::MissingReportId)
+        ));
+    }
+
+    #[test]
+    fn extend_payload_rejects_missing_report_id() {
+        assert!(matches!(
+            extend_payload(&[0x01, 2, 3], 8),
+            Err(MirajazzError::MissingReportId)
+        ));
+    }
+
+    #[test]
+    fn chunk_collector_exact_fit() {
+        let mut collector = ChunkCollector::new(4);
+
+        assert!(collector.push(&[1, 2, 3, 4]));
+        assert_eq!(collector.into_inner(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn chunk_collector_truncates_overflow() {
+        let mut collector = ChunkCollector::new(3);
+
+        assert!(collector.push(&[1, 2, 3, 4, 5]));
+        assert_eq!(collector.remaining(), 0);
+        assert_eq!(collector.into_inner(), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn chunk_collector_reassembles_chunks() {
+        let mut collector = ChunkCollector::new(10);
+
+        assert!(!collector.push(&[1, 2, 3, 4]));
+        assert_eq!(collector.remaining(), 6);
+        assert!(!collector.push(&[5, 6, 7, 8]));
+        assert!(collector.push(&[9, 10, 0, 0]));
+        assert_eq!(collector.into_inner(), (1..=10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn chunk_collector_expecting_nothing_is_complete() {
+        let collector = ChunkCollector::new(0);
+
+        assert!(collector.is_complete());
+        assert!(collector.into_inner().is_empty());
+    }
+}
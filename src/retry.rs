@@ -0,0 +1,235 @@
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::time::{self, Instant};
+
+use crate::error::MirajazzError;
+
+/// How failed operations are retried, see [with_policy]
+#[derive(Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    /// Maximal number of attempts, including the first one
+    pub max_attempts: usize,
+    /// Delay before the second attempt, doubled for every following one
+    pub base_delay: Duration,
+    /// Upper bound of the delay between attempts
+    pub max_delay: Duration,
+    /// Upper bound of random delay added to every delay, so devices don't retry in lockstep
+    pub jitter: Duration,
+    /// Gives up early if the next attempt would start after this much time since the first one
+    pub deadline: Option<Duration>,
+    /// Tells if the error is worth retrying
+    pub is_retryable: fn(&MirajazzError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(50),
+            max_delay: Duration::from_secs(1),
+            jitter: Duration::ZERO,
+            deadline: None,
+            is_retryable: MirajazzError::is_transient,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy that runs the operation only once
+    pub fn never() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Policy for connecting right after the device was plugged in, when its node may not
+    /// be ready or even enumerated yet: 5 attempts over about a second
+    ///
+    /// Retries any HID error and [MirajazzError::DeviceNotFoundError] on top of transient errors
+    pub fn plug_in() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(400),
+            is_retryable: |error| {
+                error.is_transient()
+                    || matches!(
+                        error,
+                        MirajazzError::HidError(_) | MirajazzError::DeviceNotFoundError
+                    )
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Returns delay after provided number of failed attempts, without jitter
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let factor = 1u32.checked_shl(attempt.saturating_sub(1) as u32);
+
+        factor
+            .and_then(|factor| self.base_delay.checked_mul(factor))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay)
+    }
+
+    fn jitter(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+
+        // Good enough for spreading retries, no need for a proper random generator
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.subsec_nanos() as u128);
+
+        Duration::from_nanos((nanos % self.jitter.as_nanos().max(1)) as u64)
+    }
+}
+
+/// Runs the operation, retrying it with backoff while it fails with retryable errors
+///
+/// Returns the first success, the first error that isn't retryable, or the last error
+/// when attempts or the deadline run out
+pub async fn with_policy<T, F, Fut>(
+    policy: &RetryPolicy,
+    mut operation: F,
+) -> Result<T, MirajazzError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, MirajazzError>>,
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+
+    loop {
+        let error = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        attempt += 1;
+
+        if attempt >= policy.max_attempts || !(policy.is_retryable)(&error) {
+            return Err(error);
+        }
+
+        let delay = policy.delay(attempt) + policy.jitter();
+
+        if let Some(deadline) = policy.deadline {
+            if started.elapsed() + delay > deadline {
+                return Err(error);
+            }
+        }
+
+        time::sleep(delay).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Operation failing with provided errors first, then succeeding, records when attempts started
+    fn failing_then_succeeding(
+        errors: Vec<MirajazzError>,
+        attempts: &Arc<Mutex<Vec<Duration>>>,
+    ) -> impl FnMut() -> std::future::Ready<Result<usize, MirajazzError>> {
+        let started = Instant::now();
+        let attempts = attempts.clone();
+        let mut errors = errors.into_iter();
+
+        move || {
+            let mut attempts = attempts.lock().unwrap();
+            attempts.push(started.elapsed());
+
+            std::future::ready(match errors.next() {
+                Some(error) => Err(error),
+                None => Ok(attempts.len()),
+            })
+        }
+    }
+
+    fn not_ready(count: usize) -> Vec<MirajazzError> {
+        (0..count).map(|_| MirajazzError::NotReady).collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_transient_errors_with_backoff() {
+        let attempts = Arc::default();
+        let operation = failing_then_succeeding(
+            vec![MirajazzError::NotReady, MirajazzError::BadData],
+            &attempts,
+        );
+
+        let result = with_policy(&RetryPolicy::default(), operation).await;
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(
+            *attempts.lock().unwrap(),
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(50),
+                Duration::from_millis(150)
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn returns_last_error_when_attempts_run_out() {
+        let attempts = Arc::default();
+        let operation = failing_then_succeeding(not_ready(5), &attempts);
+
+        let result = with_policy(&RetryPolicy::default(), operation).await;
+
+        assert!(matches!(result, Err(MirajazzError::NotReady)));
+        assert_eq!(attempts.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_permanent_errors() {
+        let attempts = Arc::default();
+        let operation = failing_then_succeeding(vec![MirajazzError::NoKeys], &attempts);
+
+        let result = with_policy(&RetryPolicy::default(), operation).await;
+
+        assert!(matches!(result, Err(MirajazzError::NoKeys)));
+        assert_eq!(*attempts.lock().unwrap(), vec![Duration::ZERO]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_before_deadline() {
+        let attempts = Arc::default();
+        let operation = failing_then_succeeding(not_ready(5), &attempts);
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            deadline: Some(Duration::from_millis(120)),
+            ..RetryPolicy::default()
+        };
+
+        let result = with_policy(&policy, operation).await;
+
+        assert!(matches!(result, Err(MirajazzError::NotReady)));
+        assert_eq!(
+            *attempts.lock().unwrap(),
+            vec![Duration::ZERO, Duration::from_millis(50)]
+        );
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(350));
+        assert_eq!(policy.delay(100), Duration::from_millis(350));
+    }
+}
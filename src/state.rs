@@ -1,16 +1,187 @@
-use async_hid::{AsyncHidRead, DeviceReader};
-use futures_lite::FutureExt;
-use std::{iter::zip, sync::Arc, time::Duration};
-use tokio::{sync::Mutex, time};
+use async_hid::{AsyncHidRead, AsyncHidWrite, DeviceReader, DeviceWriter, HidError, HidResult};
+use futures_lite::future;
+use log::warn;
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    iter::zip,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, PoisonError,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, Mutex, MutexGuard, Notify},
+    time::{self, Instant},
+};
 
-use crate::{error::MirajazzError, types::DeviceInput};
+use crate::{
+    error::MirajazzError,
+    protocol::{
+        classify_notification, extend_payload, is_commit_ack, is_noop_report, parse_render_error,
+        NAK,
+    },
+    stats::SharedStats,
+    types::{
+        ButtonBitmap, Capability, DeviceInput, DeviceNotification, EncoderMode, InputMode,
+        ObservedCapabilities, ProtocolVersion, PulseDirection, ReadPolicy, VirtualKey,
+        VirtualTrigger,
+    },
+};
+
+/// How many reports that carry nothing are skipped in a row before the read returns anyway,
+/// so a device spamming them can't hold the caller forever
+pub(crate) const MAX_SKIPPED_REPORTS: usize = 32;
+
+/// Lock held while writing to the device, so nothing gets in between packets of a transfer
+///
+/// Waiting writes are served in the order they started waiting. Commands take the lock for
+/// a single packet and images for a single key, so a command issued during a flush is sent
+/// after the key being transferred, before the rest of the keys
+#[derive(Debug, Default)]
+pub struct TransferLock {
+    lock: Mutex<()>,
+    waiting: AtomicUsize,
+}
+
+impl TransferLock {
+    /// Waits for the lock, recording peak amount of waiting writes in stats
+    pub async fn lock(&self, stats: &SharedStats) -> MutexGuard<'_, ()> {
+        let waiting = self.waiting.fetch_add(1, Ordering::Relaxed) + 1;
+
+        stats.update(|stats| stats.transfer_queue_peak = stats.transfer_queue_peak.max(waiting));
+
+        let guard = self.lock.lock().await;
+
+        self.waiting.fetch_sub(1, Ordering::Relaxed);
+
+        guard
+    }
+
+    /// Returns amount of writes waiting for the lock
+    pub fn queue_depth(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+}
+
+/// How many acknowledgments nobody waited for are kept, older ones are dropped
+const MAX_PENDING_ACKS: usize = 16;
+
+/// Acknowledgments and rejections of writes that readers received instead of the device
+///
+/// A reader waiting for input holds the input report stream, so replies to writes reach it first.
+/// Readers hand them over here, where the write waiting for them picks them up
+#[derive(Debug, Default)]
+pub struct AckMailbox {
+    reports: std::sync::Mutex<VecDeque<Vec<u8>>>,
+    notify: Notify,
+    /// Amount of writes currently waiting for a reply
+    expecting: AtomicUsize,
+}
+
+/// Write waiting for a reply, see [AckMailbox::expect]
+pub struct ExpectedAck<'a>(&'a AckMailbox);
+
+impl Drop for ExpectedAck<'_> {
+    fn drop(&mut self) {
+        self.0.expecting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl AckMailbox {
+    /// Marks that a write is going to wait for a reply, until the returned guard is dropped
+    ///
+    /// Replies nobody waited for are dropped
+    pub fn expect(&self) -> ExpectedAck<'_> {
+        self.clear();
+        self.expecting.fetch_add(1, Ordering::Relaxed);
+
+        ExpectedAck(self)
+    }
+
+    /// Checks if a write is waiting for a reply
+    pub fn is_expecting(&self) -> bool {
+        self.expecting.load(Ordering::Relaxed) > 0
+    }
+
+    /// Hands the report over if a write waits for a reply and the report looks like one,
+    /// returns whether it was taken
+    ///
+    /// Acknowledgments look like reports without pressed keys, so they are only taken while expected
+    pub fn offer(&self, report: &[u8]) -> bool {
+        let is_reply = report.starts_with(&NAK)
+            || is_commit_ack(report)
+            || parse_render_error(report).is_some();
+
+        if !is_reply || !self.is_expecting() {
+            return false;
+        }
+
+        self.push(report.to_vec());
+
+        true
+    }
+
+    /// Hands over the reply to whoever waits for it
+    pub fn push(&self, report: Vec<u8>) {
+        {
+            let mut reports = self.reports.lock().unwrap_or_else(PoisonError::into_inner);
+
+            if reports.len() == MAX_PENDING_ACKS {
+                reports.pop_front();
+            }
+
+            reports.push_back(report);
+        }
+
+        self.notify.notify_waiters();
+    }
+
+    /// Takes the oldest reply, if there is one
+    pub fn take(&self) -> Option<Vec<u8>> {
+        self.reports
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()
+    }
+
+    /// Drops replies nobody waited for, called before a write that is going to wait for its own
+    pub fn clear(&self) {
+        self.reports
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// Waits for the next reply
+    pub async fn wait(&self) -> Vec<u8> {
+        loop {
+            let notified = self.notify.notified();
+
+            if let Some(report) = self.take() {
+                return report;
+            }
+
+            notified.await;
+        }
+    }
+}
 
 /// Tells what changed in button states
-#[derive(Copy, Clone, Debug, Hash)]
+///
+/// New kinds of updates are added over time, so matches need a catch-all arm,
+/// [DeviceStateUpdate::key] and [DeviceStateUpdate::encoder] cover most uses without matching
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum DeviceStateUpdate {
     /// Button got pressed down
     ButtonDown(u8),
 
+    /// Touch point got pressed down at X coordinate, follows [DeviceStateUpdate::ButtonDown]
+    ButtonDownAt(u8, u16),
+
     /// Button got released
     ButtonUp(u8),
 
@@ -22,118 +193,168 @@ pub enum DeviceStateUpdate {
 
     /// Encoder was twisted
     EncoderTwist(u8, i8),
-}
 
-#[derive(Default)]
-pub struct DeviceState {
-    /// Buttons include Touch Points state
-    pub buttons: Vec<bool>,
-    pub encoders: Vec<bool>,
-}
+    /// Device reported a change of its own status
+    Notification(DeviceNotification),
 
-/// Button reader that keeps state of the device and returns events instead of full states
-/// You can only have one active reader per device at a time
-pub struct DeviceStateReader {
-    pub protocol_version: usize,
-    pub supports_both_keypress_states: bool,
-    pub supports_both_encoder_states: bool,
-    pub reader: Arc<Mutex<DeviceReader>>,
-    pub states: Mutex<DeviceState>,
-    pub process_input: fn(u8, u8) -> Result<DeviceInput, MirajazzError>,
+    /// Device reported input of the class for the first time, precedes the input.
+    /// Only sent if device was configured with [crate::device::Device::with_capability_events]
+    CapabilityObserved(Capability),
+
+    /// Virtual key got pressed down, see [crate::device::Device::with_virtual_keys]
+    VirtualKeyDown(u8),
+
+    /// Virtual key got released
+    VirtualKeyUp(u8),
 }
 
-impl DeviceStateReader {
-    /// Reads data from device
-    pub async fn raw_read_data(&self, length: usize) -> Result<Vec<u8>, MirajazzError> {
-        let mut buf = vec![0u8; length];
+/// Input that produced the update, telling apart keys, encoders and touch points with the same index
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InputId {
+    /// Key, including touch points reported as keys
+    Key(u8),
+    /// Encoder
+    Encoder(u8),
+    /// Touch point that reported the coordinate of the touch
+    TouchKey(u8),
+    /// Virtual key, beyond the physical ones
+    VirtualKey(u8),
+}
 
-        let _size = self.reader.lock().await.read_input_report(&mut buf).await?;
+impl DeviceStateUpdate {
+    /// Returns input that produced the update, [None] for notifications
+    pub fn input_id(&self) -> Option<InputId> {
+        match self {
+            DeviceStateUpdate::ButtonDown(key) | DeviceStateUpdate::ButtonUp(key) => {
+                Some(InputId::Key(*key))
+            }
+            DeviceStateUpdate::ButtonDownAt(key, _) => Some(InputId::TouchKey(*key)),
+            DeviceStateUpdate::EncoderDown(encoder)
+            | DeviceStateUpdate::EncoderUp(encoder)
+            | DeviceStateUpdate::EncoderTwist(encoder, _) => Some(InputId::Encoder(*encoder)),
+            DeviceStateUpdate::VirtualKeyDown(key) | DeviceStateUpdate::VirtualKeyUp(key) => {
+                Some(InputId::VirtualKey(*key))
+            }
+            DeviceStateUpdate::Notification(_) | DeviceStateUpdate::CapabilityObserved(_) => None,
+        }
+    }
 
-        Ok(buf)
+    /// Returns true if update is a key being pressed or released
+    pub fn is_button_event(&self) -> bool {
+        matches!(
+            self,
+            DeviceStateUpdate::ButtonDown(_)
+                | DeviceStateUpdate::ButtonDownAt(..)
+                | DeviceStateUpdate::ButtonUp(_)
+        )
     }
 
-    /// Reads data from device with specified timeout
-    ///
-    /// Returns [Some] if there was any data, returns [None] if timeout was reached before reading something
-    pub async fn raw_read_data_with_timeout(
-        &self,
-        length: usize,
-        timeout: Duration,
-    ) -> Result<Option<Vec<u8>>, MirajazzError> {
-        let mut buf = vec![0u8; length];
+    /// Returns true if update is an encoder being pressed, released or twisted
+    pub fn is_encoder_event(&self) -> bool {
+        matches!(
+            self,
+            DeviceStateUpdate::EncoderDown(_)
+                | DeviceStateUpdate::EncoderUp(_)
+                | DeviceStateUpdate::EncoderTwist(..)
+        )
+    }
 
-        let size = self
-            .reader
-            .lock()
-            .await
-            .read_input_report(&mut buf)
-            .or(async {
-                time::sleep(timeout).await;
-                Ok(0)
-            })
-            .await?;
+    /// Returns true if update is a status notification from the device
+    pub fn is_notification(&self) -> bool {
+        matches!(self, DeviceStateUpdate::Notification(_))
+    }
 
-        if size == 0 {
-            return Ok(None);
+    /// Returns key index, [None] if update isn't about a key
+    pub fn key(&self) -> Option<u8> {
+        match self.input_id()? {
+            InputId::Key(key) | InputId::TouchKey(key) | InputId::VirtualKey(key) => Some(key),
+            InputId::Encoder(_) => None,
         }
-
-        Ok(Some(buf))
     }
 
-    /// Reads current input state from the device and calls provided function for processing
-    pub async fn read_input(
-        &self,
-        timeout: Option<Duration>,
-        process_input: fn(u8, u8) -> Result<DeviceInput, MirajazzError>,
-    ) -> Result<DeviceInput, MirajazzError> {
-        let data = if timeout.is_some() {
-            self.raw_read_data_with_timeout(512, timeout.unwrap())
-                .await?
-        } else {
-            Some(self.raw_read_data(512).await?)
-        };
-
-        if data.is_none() {
-            return Ok(DeviceInput::NoData);
+    /// Returns encoder index, [None] if update isn't about an encoder
+    pub fn encoder(&self) -> Option<u8> {
+        match self.input_id()? {
+            InputId::Encoder(encoder) => Some(encoder),
+            _ => None,
         }
+    }
+}
 
-        let data = data.unwrap();
+/// Result of a single read
+#[derive(Clone, Debug)]
+pub enum ReadOutcome {
+    /// Device sent a report, updates may be empty if nothing relevant changed
+    Events(Vec<DeviceStateUpdate>),
 
-        // Skip this check if protocol version is 0, because devices with very old firmware
-        // do not prefix packets with ACK (65 67 75)
-        if !data.starts_with(&[65, 67, 75]) && self.protocol_version > 0 {
-            return Ok(DeviceInput::NoData);
-        }
+    /// Timeout was reached before device sent anything
+    Idle,
+}
 
-        let state = if self.supports_both_keypress_states {
-            data[10]
-        } else {
-            0x1u8
-        };
+/// Function that maps raw device inputs to [DeviceInput]
+#[derive(Copy, Clone, Debug)]
+pub enum InputParser {
+    /// Receives key index and its state from the report
+    Key(fn(u8, u8) -> Result<DeviceInput, MirajazzError>),
 
-        Ok(process_input(data[9], state)?)
-    }
+    /// Receives the whole input report, for devices reporting multiple inputs at once,
+    /// like simultaneous twists of several encoders
+    Report(fn(&[u8]) -> Result<DeviceInput, MirajazzError>),
 
-    /// Reads states and returns updates
-    pub async fn read(
-        &self,
-        timeout: Option<Duration>,
-    ) -> Result<Vec<DeviceStateUpdate>, MirajazzError> {
-        let input = self.read_input(timeout, self.process_input).await?;
+    /// Reads state of every button from the bitmap in the report
+    Bitmap(ButtonBitmap),
+}
 
-        Ok(self.input_to_updates(input).await)
-    }
+#[derive(Default)]
+pub struct DeviceState {
+    /// Buttons include Touch Points state
+    pub buttons: Vec<bool>,
+    pub encoders: Vec<bool>,
+}
 
-    async fn input_to_updates(&self, input: DeviceInput) -> Vec<DeviceStateUpdate> {
-        let mut my_states = self.states.lock().await;
+impl DeviceState {
+    /// Applies input to the state and returns what changed
+    ///
+    /// Returns [MirajazzError::UnexpectedInput] for inputs of the kind device has none of,
+    /// [MirajazzError::InvalidKeyIndex] and [MirajazzError::InvalidEncoderIndex] for inputs
+    /// with more keys or encoders than the state has
+    pub fn apply(
+        &mut self,
+        input: DeviceInput,
+        supports_both_keypress_states: bool,
+        supports_both_encoder_states: bool,
+    ) -> Result<Vec<DeviceStateUpdate>, MirajazzError> {
         let mut updates = vec![];
 
         match input {
+            DeviceInput::ButtonStateChange(_) | DeviceInput::TouchKey { .. }
+                if self.buttons.is_empty() =>
+            {
+                return Err(MirajazzError::UnexpectedInput);
+            }
+
+            DeviceInput::EncoderStateChange(_) | DeviceInput::EncoderTwist(_)
+                if self.encoders.is_empty() =>
+            {
+                return Err(MirajazzError::UnexpectedInput);
+            }
+
+            DeviceInput::ButtonStateChange(buttons) if buttons.len() > self.buttons.len() => {
+                return Err(MirajazzError::InvalidKeyIndex);
+            }
+
+            DeviceInput::EncoderStateChange(encoders) if encoders.len() > self.encoders.len() => {
+                return Err(MirajazzError::InvalidEncoderIndex);
+            }
+
+            DeviceInput::EncoderTwist(twist) if twist.len() > self.encoders.len() => {
+                return Err(MirajazzError::InvalidEncoderIndex);
+            }
+
             DeviceInput::ButtonStateChange(buttons) => {
-                for (index, (their, mine)) in
-                    zip(buttons.iter(), my_states.buttons.iter()).enumerate()
-                {
-                    if !self.supports_both_keypress_states {
+                for (index, (their, mine)) in zip(buttons.iter(), self.buttons.iter()).enumerate() {
+                    if !supports_both_keypress_states {
                         if *their {
                             updates.push(DeviceStateUpdate::ButtonDown(index as u8));
                             updates.push(DeviceStateUpdate::ButtonUp(index as u8));
@@ -147,14 +368,40 @@ impl DeviceStateReader {
                     }
                 }
 
-                my_states.buttons = buttons;
+                self.buttons = buttons;
+            }
+
+            DeviceInput::TouchKey { index, x, pressed } => {
+                let mine = match self.buttons.get_mut(index as usize) {
+                    Some(mine) => mine,
+                    None => return Err(MirajazzError::InvalidKeyIndex),
+                };
+
+                if pressed && (!supports_both_keypress_states || !*mine) {
+                    updates.push(DeviceStateUpdate::ButtonDown(index));
+
+                    if let Some(x) = x {
+                        updates.push(DeviceStateUpdate::ButtonDownAt(index, x));
+                    }
+                }
+
+                if !supports_both_keypress_states {
+                    if pressed {
+                        updates.push(DeviceStateUpdate::ButtonUp(index));
+                    }
+                } else if !pressed && *mine {
+                    updates.push(DeviceStateUpdate::ButtonUp(index));
+                }
+
+                if supports_both_keypress_states {
+                    *mine = pressed;
+                }
             }
 
             DeviceInput::EncoderStateChange(encoders) => {
-                for (index, (their, mine)) in
-                    zip(encoders.iter(), my_states.encoders.iter()).enumerate()
+                for (index, (their, mine)) in zip(encoders.iter(), self.encoders.iter()).enumerate()
                 {
-                    if !self.supports_both_encoder_states {
+                    if !supports_both_encoder_states {
                         if *their {
                             updates.push(DeviceStateUpdate::EncoderDown(index as u8));
                             updates.push(DeviceStateUpdate::EncoderUp(index as u8));
@@ -168,9 +415,10 @@ impl DeviceStateReader {
                     }
                 }
 
-                my_states.encoders = encoders;
+                self.encoders = encoders;
             }
 
+            // Every encoder twisted in the same report produces its own update
             DeviceInput::EncoderTwist(twist) => {
                 for (index, change) in twist.iter().enumerate() {
                     if *change != 0 {
@@ -178,11 +426,1134 @@ impl DeviceStateReader {
                     }
                 }
             }
+
+            DeviceInput::Notification(notification) => {
+                updates.push(DeviceStateUpdate::Notification(notification));
+            }
             _ => {}
         }
 
-        drop(my_states);
+        Ok(updates)
+    }
+}
+
+/// Reading half of the connection to the device
+pub enum InputTransport {
+    /// HID interface of the device
+    Hid(DeviceReader),
+    /// Reports fed by [crate::testing::ScriptedDevice], reads fail with
+    /// [async_hid::HidError::Disconnected] once it's dropped
+    Scripted(UnboundedReceiver<Vec<u8>>),
+}
 
-        updates
+impl AsyncHidRead for InputTransport {
+    async fn read_input_report<'a>(&'a mut self, buf: &'a mut [u8]) -> HidResult<usize> {
+        match self {
+            InputTransport::Hid(reader) => reader.read_input_report(buf).await,
+            InputTransport::Scripted(reports) => match reports.recv().await {
+                Some(report) => {
+                    let size = report.len().min(buf.len());
+                    buf[..size].copy_from_slice(&report[..size]);
+
+                    Ok(size)
+                }
+                None => Err(HidError::Disconnected),
+            },
+        }
+    }
+}
+
+/// Writing half of the connection to the device
+pub enum OutputTransport {
+    /// HID interface of the device
+    Hid(DeviceWriter),
+    /// Writes are discarded, for [crate::testing::ScriptedDevice]
+    Discard,
+}
+
+impl AsyncHidWrite for OutputTransport {
+    async fn write_output_report<'a>(&'a mut self, buf: &'a [u8]) -> HidResult<()> {
+        match self {
+            OutputTransport::Hid(writer) => writer.write_output_report(buf).await,
+            OutputTransport::Discard => Ok(()),
+        }
+    }
+}
+
+/// Button reader that keeps state of the device and returns events instead of full states
+/// You can only have one active reader per device at a time
+pub struct DeviceStateReader {
+    pub protocol_version: ProtocolVersion,
+    pub input_report_size: usize,
+    pub supports_both_keypress_states: bool,
+    pub supports_both_encoder_states: bool,
+    pub reader: Arc<Mutex<InputTransport>>,
+    /// Used for sending status requests in [InputMode::Polled]
+    pub writer: Arc<Mutex<OutputTransport>>,
+    /// Shared with the device, so status requests don't get in between image packets
+    pub transfer_lock: Arc<TransferLock>,
+    /// Shared with the device, can change if device negotiates packet size
+    pub packet_size: Arc<AtomicUsize>,
+    pub input_mode: InputMode,
+    pub read_policy: ReadPolicy,
+    pub states: std::sync::Mutex<DeviceState>,
+    pub process_input: InputParser,
+    /// Statistics shared with the device
+    pub stats: SharedStats,
+    /// Input reports the device received while waiting for responses, processed before reading new ones
+    pub queued_reports: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    pub encoder_mode: EncoderMode,
+    /// When currently pressed pulse buttons went down, for [EncoderMode::PulseAsTwist]
+    pub pulses: std::sync::Mutex<HashMap<u8, Instant>>,
+    /// Logical index of every physical key, shared with the device, empty if layout isn't rotated
+    pub logical_keys: Arc<std::sync::RwLock<Vec<u8>>>,
+    /// Key index of every raw key code, empty to pass codes as is
+    pub key_codes: HashMap<u8, u8>,
+    /// Send [DeviceStateUpdate::CapabilityObserved] when input class is seen for the first time
+    pub capability_events: bool,
+    /// Shared with the device, replies to writes are handed over there instead of being parsed as input
+    pub acks: Arc<AckMailbox>,
+    /// Keys reported beyond the physical ones, empty to report inputs as is
+    pub virtual_keys: Vec<VirtualKey>,
+    /// Inputs currently holding virtual keys down and when keys went down, for long presses
+    pub virtual_state: std::sync::Mutex<VirtualKeyState>,
+    /// Read left unfinished by non-blocking reads, shared with the device
+    pub pending_read: Arc<PendingRead>,
+}
+
+/// What inputs are holding virtual keys down, so every press gets its release
+#[derive(Debug, Default)]
+pub struct VirtualKeyState {
+    /// Virtual key held by each input
+    pub held: HashMap<InputId, u8>,
+    /// When keys with long press triggers went down
+    pub pressed_at: HashMap<u8, Instant>,
+}
+
+/// Reads single input report into a buffer of provided length, keeping only the bytes that were read
+///
+/// Waiting for the reader counts towards the timeout. Returns [None] if timeout was reached
+pub(crate) async fn read_sized_report<R: AsyncHidRead>(
+    reader: &Mutex<R>,
+    length: usize,
+    timeout: Duration,
+) -> Result<Option<Vec<u8>>, MirajazzError> {
+    let mut buf = vec![0u8; length];
+
+    let read = async {
+        let mut reader = reader.lock().await;
+
+        reader.read_input_report(&mut buf).await
+    };
+
+    let size = match time::timeout(timeout, read).await {
+        Ok(size) => size?,
+        Err(_) => 0,
+    };
+
+    if size == 0 {
+        return Ok(None);
+    }
+
+    buf.truncate(size);
+
+    Ok(Some(buf))
+}
+
+/// Read that owns the reader and the buffer, so it can be resumed by any later read
+type OwnedRead = Pin<Box<dyn Future<Output = Result<Vec<u8>, MirajazzError>> + Send>>;
+
+/// Input report read started by a non-blocking attempt, kept until it completes
+///
+/// Dropping an unfinished read loses the report on some backends, so a read that isn't ready
+/// right away is polled again by the next attempt, or awaited by the next blocking read. The
+/// read holds the reader until it completes
+#[derive(Default)]
+pub struct PendingRead {
+    read: std::sync::Mutex<Option<OwnedRead>>,
+}
+
+impl std::fmt::Debug for PendingRead {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingRead")
+            .field("pending", &self.lock().is_some())
+            .finish()
+    }
+}
+
+/// Puts the read back if it was cancelled or timed out before completing
+struct RestoreRead<'a> {
+    pending: &'a PendingRead,
+    read: Option<OwnedRead>,
+}
+
+impl Drop for RestoreRead<'_> {
+    fn drop(&mut self) {
+        if let Some(read) = self.read.take() {
+            *self.pending.lock() = Some(read);
+        }
+    }
+}
+
+impl PendingRead {
+    /// Polls the pending read once, starting a new one if there's none, and never waits
+    ///
+    /// Returns [None] if no report is available yet, or if another read holds the reader.
+    /// Backends registering reads with the tokio reactor need to be called within a runtime context
+    pub fn poll_now<R>(
+        &self,
+        reader: &Arc<Mutex<R>>,
+        length: usize,
+    ) -> Result<Option<Vec<u8>>, MirajazzError>
+    where
+        R: AsyncHidRead + Send + 'static,
+    {
+        let mut pending = self.lock();
+
+        let mut read = match pending.take() {
+            Some(read) => read,
+            None => match reader.clone().try_lock_owned() {
+                Ok(mut reader) => Box::pin(async move {
+                    let mut buf = vec![0u8; length];
+
+                    let size = reader.read_input_report(&mut buf).await?;
+
+                    buf.truncate(size);
+
+                    Ok(buf)
+                }),
+                Err(_) => return Ok(None),
+            },
+        };
+
+        match future::block_on(future::poll_once(&mut read)) {
+            Some(data) => Ok(Some(data?).filter(|data| !data.is_empty())),
+            None => {
+                *pending = Some(read);
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Reads single report, finishing the pending read first if there is one
+    ///
+    /// Same as [read_sized_report] otherwise, waits without a limit if timeout is [None]
+    pub(crate) async fn read<R: AsyncHidRead>(
+        &self,
+        reader: &Mutex<R>,
+        length: usize,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Vec<u8>>, MirajazzError> {
+        let read = self.lock().take();
+
+        let mut restore = match (read, timeout) {
+            (Some(read), _) => RestoreRead {
+                pending: self,
+                read: Some(read),
+            },
+            (None, Some(timeout)) => return read_sized_report(reader, length, timeout).await,
+            (None, None) => {
+                let mut buf = vec![0u8; length];
+
+                let size = reader.lock().await.read_input_report(&mut buf).await?;
+
+                buf.truncate(size);
+
+                return Ok(Some(buf).filter(|data| !data.is_empty()));
+            }
+        };
+
+        let read = restore
+            .read
+            .as_mut()
+            .expect("read is taken only on completion");
+
+        let data = match timeout {
+            Some(timeout) => match time::timeout(timeout, read).await {
+                Ok(data) => data,
+                Err(_) => return Ok(None),
+            },
+            None => read.await,
+        };
+
+        restore.read = None;
+
+        Ok(Some(data?).filter(|data| !data.is_empty()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<OwnedRead>> {
+        self.read.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl DeviceStateReader {
+    /// Reads data from device
+    pub async fn raw_read_data(&self, length: usize) -> Result<Vec<u8>, MirajazzError> {
+        let data = self.pending_read.read(&self.reader, length, None).await?;
+
+        Ok(data.unwrap_or_default())
+    }
+
+    /// Reads data from device with specified timeout
+    ///
+    /// Returns [Some] if there was any data, returns [None] if timeout was reached before reading something
+    pub async fn raw_read_data_with_timeout(
+        &self,
+        length: usize,
+        timeout: Duration,
+    ) -> Result<Option<Vec<u8>>, MirajazzError> {
+        self.pending_read
+            .read(&self.reader, length, Some(timeout))
+            .await
+    }
+
+    /// Reads data from device if some is available right away, without waiting and without a timer
+    ///
+    /// Returns [None] if there's no data, or if another read is in progress. A read that isn't
+    /// ready stays pending and is picked up by the next read, see [PendingRead]
+    pub fn try_raw_read_data(&self, length: usize) -> Result<Option<Vec<u8>>, MirajazzError> {
+        self.pending_read.poll_now(&self.reader, length)
+    }
+
+    /// Sends status request to the device, for devices that don't send input reports by themselves
+    ///
+    /// Request is validated the same way as [crate::device::Device::write_extended_data] payloads
+    async fn request_status(&self, request: &[u8]) -> Result<(), MirajazzError> {
+        let buf = extend_payload(request, self.packet_size.load(Ordering::Relaxed))?;
+
+        let _transfer = self.transfer_lock.lock(&self.stats).await;
+
+        self.writer.lock().await.write_output_report(&buf).await?;
+
+        Ok(())
+    }
+
+    /// Reads current input state from the device and calls provided function for processing
+    ///
+    /// In [InputMode::Polled], status request is sent first, and its response is processed as usual
+    pub async fn read_input(
+        &self,
+        timeout: Option<Duration>,
+        process_input: InputParser,
+    ) -> Result<DeviceInput, MirajazzError> {
+        let input = self.read_input_or_idle(timeout, process_input).await?;
+
+        Ok(input.unwrap_or(DeviceInput::NoData))
+    }
+
+    /// Reads input if some is available right away, for draining input from external poll loops
+    ///
+    /// Returns [None] immediately if there's no report. Status requests of [InputMode::Polled]
+    /// are not sent
+    pub fn try_read_input(
+        &self,
+        process_input: InputParser,
+    ) -> Result<Option<DeviceInput>, MirajazzError> {
+        let queued = match self.queued_reports.try_lock() {
+            Ok(mut queued) => queued.pop_front(),
+            Err(_) => None,
+        };
+
+        let data = match queued {
+            Some(data) => data,
+            None => match self.try_raw_read_data(self.input_report_size)? {
+                Some(data) => data,
+                None => return Ok(None),
+            },
+        };
+
+        if is_noop_report(&data) {
+            self.stats.update(|stats| stats.noop_reports += 1);
+
+            return Ok(None);
+        }
+
+        self.process_report(data, process_input).map(Some)
+    }
+
+    /// Same as [DeviceStateReader::read_input], but returns [None] if timeout was reached
+    async fn read_input_or_idle(
+        &self,
+        timeout: Option<Duration>,
+        process_input: InputParser,
+    ) -> Result<Option<DeviceInput>, MirajazzError> {
+        let queued = self.queued_reports.lock().await.pop_front();
+
+        if let Some(data) = queued {
+            return self.process_report(data, process_input).map(Some);
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // Waiting between requests counts towards the timeout, response arriving after the deadline
+        // is read by the next call
+        if let InputMode::Polled { request, interval } = &self.input_mode {
+            let interval = match deadline {
+                Some(deadline) => {
+                    (*interval).min(deadline.saturating_duration_since(Instant::now()))
+                }
+                None => *interval,
+            };
+
+            time::sleep(interval).await;
+
+            self.request_status(request).await?;
+        }
+
+        // Reports that carry nothing are skipped, up to a limit
+        for _ in 0..MAX_SKIPPED_REPORTS {
+            let started = Instant::now();
+
+            let data = if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(started);
+
+                self.raw_read_data_with_timeout(self.input_report_size, remaining)
+                    .await?
+            } else {
+                Some(self.raw_read_data(self.input_report_size).await?)
+            };
+
+            self.stats
+                .update(|stats| stats.read_input.record(started.elapsed()));
+
+            let data = match data {
+                Some(data) => data,
+                None => return Ok(None),
+            };
+
+            if !is_noop_report(&data) {
+                return self.process_report(data, process_input).map(Some);
+            }
+
+            self.stats.update(|stats| stats.noop_reports += 1);
+        }
+
+        Ok(Some(DeviceInput::NoData))
+    }
+
+    /// Maps raw input report to [DeviceInput]
+    fn process_report(
+        &self,
+        data: Vec<u8>,
+        process_input: InputParser,
+    ) -> Result<DeviceInput, MirajazzError> {
+        let taken = self.acks.offer(&data);
+
+        // Render errors are still reported as notifications
+        if let Some(notification) = classify_notification(&data) {
+            return Ok(DeviceInput::Notification(notification));
+        }
+
+        // Acknowledgments look like key reports without a key. Bitmaps and custom parsers can't tell
+        // them apart from reports without pressed keys, so they parse them anyway
+        if taken && (data.starts_with(&NAK) || matches!(process_input, InputParser::Key(_))) {
+            return Ok(DeviceInput::NoData);
+        }
+
+        // Skip this check if protocol version is 0, because devices with very old firmware
+        // do not prefix packets with ACK (65 67 75)
+        if !data.starts_with(&[65, 67, 75]) && self.protocol_version.has_ack_prefix() {
+            return Ok(DeviceInput::NoData);
+        }
+
+        let process_input = match process_input {
+            InputParser::Key(process_input) => process_input,
+            InputParser::Report(process_report) => return process_report(&data),
+            InputParser::Bitmap(bitmap) => return bitmap.parse(&data),
+        };
+
+        // Report is too short to carry key and state
+        if data.len() < 11 {
+            return Err(MirajazzError::BadData);
+        }
+
+        let state = if self.supports_both_keypress_states {
+            data[10]
+        } else {
+            0x1u8
+        };
+
+        let key = if self.key_codes.is_empty() {
+            data[9]
+        } else {
+            match self.key_codes.get(&data[9]) {
+                Some(key) => *key,
+                None => return Err(MirajazzError::BadData),
+            }
+        };
+
+        process_input(key, state)
+    }
+
+    /// Reads states and returns updates
+    ///
+    /// Malformed reports are skipped in [ReadPolicy::Lenient], and returned as [MirajazzError::BadData]
+    /// in [ReadPolicy::Strict]. So are inputs of the kind device was declared not to have, returned as
+    /// [MirajazzError::UnexpectedInput], and inputs with out of range index, returned as
+    /// [MirajazzError::InvalidKeyIndex] or [MirajazzError::InvalidEncoderIndex].
+    /// Both are counted, see [DeviceStateReader::unexpected_input_count]
+    pub async fn read(
+        &self,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<DeviceStateUpdate>, MirajazzError> {
+        match self.poll(timeout).await? {
+            ReadOutcome::Events(updates) => Ok(updates),
+            ReadOutcome::Idle => Ok(vec![]),
+        }
+    }
+
+    /// Reads states and returns updates, telling apart timeouts from reports without relevant updates
+    ///
+    /// Errors are the same as for [DeviceStateReader::read]
+    pub async fn poll(&self, timeout: Option<Duration>) -> Result<ReadOutcome, MirajazzError> {
+        let input = match self.read_input_or_idle(timeout, self.process_input).await {
+            Err(MirajazzError::BadData) if self.read_policy == ReadPolicy::Lenient => {
+                self.stats.update(|stats| stats.bad_reports += 1);
+
+                warn!("Skipping malformed input report");
+
+                return Ok(ReadOutcome::Events(vec![]));
+            }
+            result => result?,
+        };
+
+        match input {
+            Some(input) => {
+                let updates = self.skip_unexpected(self.input_to_updates(input))?;
+
+                Ok(ReadOutcome::Events(updates))
+            }
+            None => Ok(ReadOutcome::Idle),
+        }
+    }
+
+    /// Reads states and returns updates if a report is available right away, empty if there's none
+    ///
+    /// Never waits for the device and doesn't need an async context, so it can be called from
+    /// external event loops. Errors are the same as for [DeviceStateReader::read]
+    pub fn try_read(&self) -> Result<Vec<DeviceStateUpdate>, MirajazzError> {
+        let input = match self.try_read_input(self.process_input) {
+            Err(MirajazzError::BadData) if self.read_policy == ReadPolicy::Lenient => {
+                self.stats.update(|stats| stats.bad_reports += 1);
+
+                warn!("Skipping malformed input report");
+
+                return Ok(vec![]);
+            }
+            result => result?,
+        };
+
+        match input {
+            Some(input) => self.skip_unexpected(self.input_to_updates(input)),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Skips unexpected and out of range inputs in [ReadPolicy::Lenient], they are counted already
+    fn skip_unexpected(
+        &self,
+        result: Result<Vec<DeviceStateUpdate>, MirajazzError>,
+    ) -> Result<Vec<DeviceStateUpdate>, MirajazzError> {
+        match result {
+            Err(
+                MirajazzError::UnexpectedInput
+                | MirajazzError::InvalidKeyIndex
+                | MirajazzError::InvalidEncoderIndex,
+            ) if self.read_policy == ReadPolicy::Lenient => Ok(vec![]),
+            result => result,
+        }
+    }
+
+    /// Reads in a loop, calling `on_events` for every report and `on_idle` every time
+    /// `poll_timeout` passes without any reports, for doing housekeeping on the same task
+    ///
+    /// Runs until reading or one of the callbacks returns an error
+    pub async fn run<E, EF, I, IF>(
+        &self,
+        poll_timeout: Duration,
+        mut on_events: E,
+        mut on_idle: I,
+    ) -> Result<(), MirajazzError>
+    where
+        E: FnMut(Vec<DeviceStateUpdate>) -> EF,
+        EF: Future<Output = Result<(), MirajazzError>>,
+        I: FnMut() -> IF,
+        IF: Future<Output = Result<(), MirajazzError>>,
+    {
+        loop {
+            match self.poll(Some(poll_timeout)).await? {
+                ReadOutcome::Events(updates) => on_events(updates).await?,
+                ReadOutcome::Idle => on_idle().await?,
+            }
+        }
+    }
+
+    /// Returns amount of inputs device reported for the kind it was declared not to have
+    pub fn unexpected_input_count(&self) -> u64 {
+        self.stats.snapshot().unexpected_inputs
+    }
+
+    /// Records input of the kind device was declared not to have
+    fn unexpected_input(&self, kind: &str) -> MirajazzError {
+        self.stats.update(|stats| stats.unexpected_inputs += 1);
+
+        warn!(
+            "Device reported {} input, but was declared without any, check key and encoder counts",
+            kind
+        );
+
+        MirajazzError::UnexpectedInput
+    }
+
+    fn input_to_updates(
+        &self,
+        input: DeviceInput,
+    ) -> Result<Vec<DeviceStateUpdate>, MirajazzError> {
+        let kind = match input {
+            DeviceInput::ButtonStateChange(_) | DeviceInput::TouchKey { .. } => "button",
+            _ => "encoder",
+        };
+
+        let capability = match input {
+            DeviceInput::ButtonStateChange(_) => Some(Capability::Buttons),
+            DeviceInput::TouchKey { .. } => Some(Capability::Touch),
+            DeviceInput::EncoderStateChange(_) | DeviceInput::EncoderTwist(_) => {
+                Some(Capability::Encoders)
+            }
+            DeviceInput::NoData | DeviceInput::Notification(_) => None,
+        };
+
+        let result = self
+            .states
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .apply(
+                input,
+                self.supports_both_keypress_states,
+                self.supports_both_encoder_states,
+            );
+
+        let updates = match result {
+            Err(MirajazzError::UnexpectedInput) => return Err(self.unexpected_input(kind)),
+            Err(err @ (MirajazzError::InvalidKeyIndex | MirajazzError::InvalidEncoderIndex)) => {
+                self.stats.update(|stats| stats.out_of_range_inputs += 1);
+
+                warn!(
+                    "Device reported {} input beyond the declared count, check key and encoder counts",
+                    kind
+                );
+
+                return Err(err);
+            }
+            result => result?,
+        };
+
+        let updates = match &self.encoder_mode {
+            EncoderMode::Normal => updates,
+            EncoderMode::PulseAsTwist {
+                direction_map,
+                max_pulse,
+            } => self.pulses_to_twists(updates, direction_map, *max_pulse),
+        };
+
+        let updates = self.to_logical_keys(updates);
+
+        let mut updates = if self.virtual_keys.is_empty() {
+            updates
+        } else {
+            self.to_virtual_keys(updates)
+        };
+
+        if let Some(capability) = capability {
+            let mut new = false;
+
+            self.stats
+                .update(|stats| new = stats.observed_capabilities.observe(capability));
+
+            if new && self.capability_events {
+                updates.insert(0, DeviceStateUpdate::CapabilityObserved(capability));
+            }
+        }
+
+        Ok(updates)
+    }
+
+    /// Returns classes of input the device was seen reporting, by this or other readers
+    ///
+    /// Useful for hiding controls that are declared, but never reported by the firmware
+    pub fn observed_capabilities(&self) -> ObservedCapabilities {
+        self.stats.snapshot().observed_capabilities
+    }
+
+    /// Replaces physical key indices with logical ones, if device layout is rotated
+    fn to_logical_keys(&self, updates: Vec<DeviceStateUpdate>) -> Vec<DeviceStateUpdate> {
+        let logical_keys = self
+            .logical_keys
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if logical_keys.is_empty() {
+            return updates;
+        }
+
+        let logical = |key: u8| logical_keys.get(key as usize).copied().unwrap_or(key);
+
+        updates
+            .into_iter()
+            .map(|update| match update {
+                DeviceStateUpdate::ButtonDown(key) => DeviceStateUpdate::ButtonDown(logical(key)),
+                DeviceStateUpdate::ButtonDownAt(key, x) => {
+                    DeviceStateUpdate::ButtonDownAt(logical(key), x)
+                }
+                DeviceStateUpdate::ButtonUp(key) => DeviceStateUpdate::ButtonUp(logical(key)),
+                update => update,
+            })
+            .collect()
+    }
+
+    /// Replaces inputs that trigger virtual keys with virtual key updates
+    fn to_virtual_keys(&self, updates: Vec<DeviceStateUpdate>) -> Vec<DeviceStateUpdate> {
+        let mut state = self
+            .virtual_state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let mut result = Vec::with_capacity(updates.len());
+
+        for update in updates {
+            let triggered = |matches: &dyn Fn(&VirtualTrigger) -> bool| {
+                self.virtual_keys
+                    .iter()
+                    .find(|virtual_key| matches(&virtual_key.trigger))
+                    .map(|virtual_key| virtual_key.index)
+            };
+
+            match update {
+                DeviceStateUpdate::EncoderDown(encoder) => {
+                    match triggered(&|trigger| *trigger == VirtualTrigger::EncoderPress(encoder)) {
+                        Some(index) => {
+                            state.held.insert(InputId::Encoder(encoder), index);
+                            result.push(DeviceStateUpdate::VirtualKeyDown(index));
+                        }
+                        None => result.push(update),
+                    }
+                }
+                DeviceStateUpdate::EncoderUp(encoder) => {
+                    match state.held.remove(&InputId::Encoder(encoder)) {
+                        Some(index) => result.push(DeviceStateUpdate::VirtualKeyUp(index)),
+                        None => result.push(update),
+                    }
+                }
+                DeviceStateUpdate::ButtonDown(key) => {
+                    let long_press = triggered(
+                        &|trigger| matches!(trigger, VirtualTrigger::LongPress { key: k, .. } if *k == key),
+                    );
+
+                    if long_press.is_some() {
+                        state.pressed_at.insert(key, Instant::now());
+                    }
+
+                    result.push(update);
+                }
+                DeviceStateUpdate::ButtonDownAt(key, x) => {
+                    let region = triggered(&|trigger| {
+                        matches!(trigger, VirtualTrigger::TouchRegion { key: k, start, end }
+                            if *k == key && (*start..*end).contains(&x))
+                    });
+
+                    match region {
+                        Some(index) => {
+                            // Touch is reported as the virtual key instead of the touch point
+                            if matches!(result.last(), Some(DeviceStateUpdate::ButtonDown(k)) if *k == key)
+                            {
+                                result.pop();
+                            }
+
+                            state.pressed_at.remove(&key);
+                            state.held.insert(InputId::TouchKey(key), index);
+                            result.push(DeviceStateUpdate::VirtualKeyDown(index));
+                        }
+                        None => result.push(update),
+                    }
+                }
+                DeviceStateUpdate::ButtonUp(key) => {
+                    if let Some(index) = state.held.remove(&InputId::TouchKey(key)) {
+                        result.push(DeviceStateUpdate::VirtualKeyUp(index));
+                        continue;
+                    }
+
+                    result.push(update);
+
+                    let held = match state.pressed_at.remove(&key) {
+                        Some(pressed_at) => pressed_at.elapsed(),
+                        None => continue,
+                    };
+
+                    let long_press = triggered(&|trigger| {
+                        matches!(trigger, VirtualTrigger::LongPress { key: k, after }
+                            if *k == key && held >= *after)
+                    });
+
+                    if let Some(index) = long_press {
+                        result.push(DeviceStateUpdate::VirtualKeyDown(index));
+                        result.push(DeviceStateUpdate::VirtualKeyUp(index));
+                    }
+                }
+                update => result.push(update),
+            }
+        }
+
+        result
+    }
+
+    /// Replaces short pulses of mapped encoder buttons with twists
+    fn pulses_to_twists(
+        &self,
+        updates: Vec<DeviceStateUpdate>,
+        direction_map: &[PulseDirection],
+        max_pulse: Duration,
+    ) -> Vec<DeviceStateUpdate> {
+        let mut pulses = self.pulses.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut result = Vec::with_capacity(updates.len());
+
+        for update in updates {
+            let mapped = |index| direction_map.iter().find(|pulse| pulse.index == index);
+
+            match update {
+                DeviceStateUpdate::EncoderDown(index) if mapped(index).is_some() => {
+                    pulses.insert(index, Instant::now());
+                }
+                DeviceStateUpdate::EncoderUp(index) => match (mapped(index), pulses.remove(&index))
+                {
+                    (Some(pulse), Some(started)) if started.elapsed() <= max_pulse => {
+                        result.push(DeviceStateUpdate::EncoderTwist(
+                            pulse.encoder,
+                            pulse.direction,
+                        ));
+                    }
+                    (Some(_), Some(_)) => {
+                        result.push(DeviceStateUpdate::EncoderDown(index));
+                        result.push(update);
+                    }
+                    _ => result.push(update),
+                },
+                update => result.push(update),
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        testing::{InputScript, ScriptedDevice},
+        types::{BitOrder, ButtonBitmap},
+    };
+    use async_hid::HidResult;
+
+    /// Transport returning queued reports, truncated to the buffer like HID backends do
+    struct MockReader {
+        reports: VecDeque<Vec<u8>>,
+    }
+
+    impl MockReader {
+        fn new(reports: Vec<Vec<u8>>) -> Mutex<Self> {
+            Mutex::new(Self {
+                reports: reports.into(),
+            })
+        }
+    }
+
+    impl AsyncHidRead for MockReader {
+        async fn read_input_report<'a>(&'a mut self, buf: &'a mut [u8]) -> HidResult<usize> {
+            match self.reports.pop_front() {
+                Some(report) => {
+                    let size = report.len().min(buf.len());
+                    buf[..size].copy_from_slice(&report[..size]);
+
+                    Ok(size)
+                }
+                None => future::pending().await,
+            }
+        }
+    }
+
+    /// Transport delivering reports as the test makes them available
+    struct ScriptedReader {
+        reports: tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+    }
+
+    impl ScriptedReader {
+        fn new() -> (
+            Arc<Mutex<Self>>,
+            tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+        ) {
+            let (sender, reports) = tokio::sync::mpsc::unbounded_channel();
+
+            (Arc::new(Mutex::new(Self { reports })), sender)
+        }
+    }
+
+    impl AsyncHidRead for ScriptedReader {
+        async fn read_input_report<'a>(&'a mut self, buf: &'a mut [u8]) -> HidResult<usize> {
+            match self.reports.recv().await {
+                Some(report) => {
+                    let size = report.len().min(buf.len());
+                    buf[..size].copy_from_slice(&report[..size]);
+
+                    Ok(size)
+                }
+                None => future::pending().await,
+            }
+        }
+    }
+
+    #[test]
+    fn try_read_interleaves_with_report_availability() {
+        let (reader, reports) = ScriptedReader::new();
+        let pending = PendingRead::default();
+
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), None);
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), None);
+
+        reports.send(vec![1; 64]).unwrap();
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), Some(vec![1; 64]));
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), None);
+
+        reports.send(vec![2; 64]).unwrap();
+        reports.send(vec![3; 64]).unwrap();
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), Some(vec![2; 64]));
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), Some(vec![3; 64]));
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), None);
+    }
+
+    #[test]
+    fn try_read_leaves_reader_held_by_other_reads() {
+        let (reader, reports) = ScriptedReader::new();
+        let pending = PendingRead::default();
+
+        reports.send(vec![1; 64]).unwrap();
+
+        let held = reader.try_lock().unwrap();
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), None);
+
+        drop(held);
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), Some(vec![1; 64]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn blocking_read_finishes_pending_read() {
+        let (reader, reports) = ScriptedReader::new();
+        let pending = PendingRead::default();
+
+        assert_eq!(pending.poll_now(&reader, 64).unwrap(), None);
+
+        // Pending read holds the reader, so it's the one that has to receive the report
+        let read = pending.read(&reader, 64, Some(Duration::from_millis(100)));
+        assert_eq!(read.await.unwrap(), None);
+
+        reports.send(vec![1; 64]).unwrap();
+
+        let read = pending.read(&reader, 64, Some(Duration::from_millis(100)));
+        assert_eq!(read.await.unwrap(), Some(vec![1; 64]));
+
+        reports.send(vec![2; 64]).unwrap();
+
+        let read = pending.read(&reader, 64, None);
+        assert_eq!(read.await.unwrap(), Some(vec![2; 64]));
+    }
+
+    /// Plays the script, returning updates along with when they were read
+    async fn play(
+        device: &mut ScriptedDevice,
+        script: &InputScript,
+    ) -> Vec<(u64, Vec<DeviceStateUpdate>)> {
+        let started = Instant::now();
+        let mut played = vec![];
+
+        device
+            .play(script, |updates| {
+                played.push((started.elapsed().as_millis() as u64, updates))
+            })
+            .await
+            .unwrap();
+
+        played
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reader_plays_short_and_long_press() {
+        let mut device = ScriptedDevice::new(6, 0);
+
+        let played = play(&mut device, &InputScript::short_and_long_press()).await;
+
+        assert_eq!(
+            played,
+            vec![
+                (0, vec![DeviceStateUpdate::ButtonDown(0)]),
+                (100, vec![DeviceStateUpdate::ButtonUp(0)]),
+                (300, vec![DeviceStateUpdate::ButtonDown(0)]),
+                (800, vec![DeviceStateUpdate::ButtonUp(0)]),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reader_reports_long_press_as_virtual_key() {
+        let mut device = ScriptedDevice::new(6, 0).with_virtual_keys(vec![VirtualKey {
+            index: 6,
+            trigger: VirtualTrigger::LongPress {
+                key: 0,
+                after: Duration::from_millis(400),
+            },
+        }]);
+
+        let played = play(&mut device, &InputScript::short_and_long_press()).await;
+
+        assert_eq!(played[1].1, vec![DeviceStateUpdate::ButtonUp(0)]);
+        assert_eq!(
+            played[3].1,
+            vec![
+                DeviceStateUpdate::ButtonUp(0),
+                DeviceStateUpdate::VirtualKeyDown(6),
+                DeviceStateUpdate::VirtualKeyUp(6),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reader_plays_encoder_press_and_twist() {
+        let mut device = ScriptedDevice::new(6, 3).with_capability_events(true);
+
+        let played = play(&mut device, &InputScript::encoder_press_and_twist()).await;
+
+        assert_eq!(
+            played,
+            vec![
+                (
+                    0,
+                    vec![
+                        DeviceStateUpdate::CapabilityObserved(Capability::Encoders),
+                        DeviceStateUpdate::EncoderDown(0)
+                    ]
+                ),
+                (50, vec![DeviceStateUpdate::EncoderUp(0)]),
+                (200, vec![DeviceStateUpdate::EncoderTwist(0, 2)]),
+                (300, vec![DeviceStateUpdate::EncoderTwist(0, -1)]),
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reader_rejects_inputs_beyond_declared_counts() {
+        let mut device = ScriptedDevice::new(6, 0);
+
+        let result = device
+            .play(&InputScript::encoder_press_and_twist(), |_| {})
+            .await;
+
+        assert!(matches!(result, Err(MirajazzError::UnexpectedInput)));
+        assert_eq!(device.reader().unexpected_input_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn reads_reports_of_every_size() {
+        for size in [64, 512, 1024] {
+            let report: Vec<u8> = (0..size).map(|byte| byte as u8).collect();
+            let reader = MockReader::new(vec![report.clone()]);
+
+            let read = read_sized_report(&reader, size, Duration::from_secs(1)).await;
+
+            assert_eq!(read.unwrap(), Some(report));
+        }
+    }
+
+    #[tokio::test]
+    async fn short_reports_are_not_padded() {
+        for size in [64, 512, 1024] {
+            let report = b"ACK\0\0OK\0\0\x03\x01".to_vec();
+            let reader = MockReader::new(vec![report.clone()]);
+
+            let read = read_sized_report(&reader, size, Duration::from_secs(1)).await;
+
+            assert_eq!(read.unwrap(), Some(report));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeout_includes_waiting_for_reader() {
+        let reader = MockReader::new(vec![vec![1; 64]]);
+        let _held = reader.lock().await;
+
+        let read = read_sized_report(&reader, 64, Duration::from_millis(100)).await;
+
+        assert_eq!(read.unwrap(), None);
+    }
+
+    #[test]
+    fn report_size_follows_protocol_version() {
+        assert_eq!(ProtocolVersion::V1.input_report_size(), 512);
+        assert_eq!(ProtocolVersion::V2.input_report_size(), 1024);
+        assert_eq!(ProtocolVersion::V3.input_report_size(), 1024);
+    }
+
+    /// Bitmap report with every key released, byte 9 is zero like in commit acknowledgments
+    fn released_bitmap_report() -> Vec<u8> {
+        let mut report = vec![0u8; 64];
+        report[..3].copy_from_slice(b"ACK");
+
+        report
+    }
+
+    const BITMAP: ButtonBitmap = ButtonBitmap {
+        offset: 72,
+        bit_order: BitOrder::LsbFirst,
+        key_count: 15,
+    };
+
+    #[test]
+    fn bitmap_release_is_not_taken_without_waiting_write() {
+        let acks = AckMailbox::default();
+        let report = released_bitmap_report();
+
+        assert!(!acks.offer(&report));
+        assert!(acks.take().is_none());
+
+        match BITMAP.parse(&report) {
+            Ok(DeviceInput::ButtonStateChange(buttons)) => {
+                assert_eq!(buttons, vec![false; 15]);
+            }
+            other => panic!("unexpected input {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ack_is_taken_while_write_waits() {
+        let acks = AckMailbox::default();
+        let report = released_bitmap_report();
+
+        {
+            let _expected = acks.expect();
+
+            assert!(acks.offer(&report));
+        }
+
+        assert_eq!(acks.take(), Some(report.clone()));
+        assert!(!acks.offer(&report));
+    }
+
+    #[test]
+    fn key_reports_are_never_taken() {
+        let acks = AckMailbox::default();
+        let _expected = acks.expect();
+
+        let mut report = released_bitmap_report();
+        report[9] = 3;
+        report[10] = 1;
+
+        assert!(!acks.offer(&report));
     }
 }
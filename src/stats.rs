@@ -0,0 +1,93 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::types::ObservedCapabilities;
+
+/// Running statistics of operation durations
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DurationStats {
+    /// Amount of recorded operations
+    pub count: u64,
+    /// Shortest operation
+    pub min: Duration,
+    /// Longest operation
+    pub max: Duration,
+    /// Total time spent in operations
+    pub total: Duration,
+}
+
+impl DurationStats {
+    /// Records duration of a single operation
+    pub fn record(&mut self, duration: Duration) {
+        if self.count == 0 || duration < self.min {
+            self.min = duration;
+        }
+
+        self.max = self.max.max(duration);
+        self.total += duration;
+        self.count += 1;
+    }
+
+    /// Returns average duration of the operation, if anything was recorded
+    pub fn average(&self) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        Some(Duration::from_nanos(
+            (self.total.as_nanos() / self.count as u128) as u64,
+        ))
+    }
+}
+
+/// Statistics of the device operations
+#[derive(Clone, Debug, Default)]
+pub struct DeviceStats {
+    /// Writing single packet to the device
+    pub packet_write: DurationStats,
+    /// Sending whole image of a single key
+    pub key_transfer: DurationStats,
+    /// Full flush, including the commit
+    pub flush: DurationStats,
+    /// Waiting for the input report
+    pub read_input: DurationStats,
+    /// Amount of inputs device reported for the kind it was declared not to have
+    pub unexpected_inputs: u64,
+    /// Amount of inputs with key or encoder index beyond the declared count
+    pub out_of_range_inputs: u64,
+    /// Amount of malformed input reports skipped in lenient mode
+    pub bad_reports: u64,
+    /// Amount of commits that weren't acknowledged in time
+    pub commit_ack_timeouts: u64,
+    /// Amount of empty, all-zero and keep-alive echo reports skipped by readers
+    pub noop_reports: u64,
+    /// Amount of image transfers rejected by the device because of checksum mismatch
+    pub checksum_mismatches: u64,
+    /// Packet size the device acknowledged, see [crate::device::Device::with_packet_size_negotiation]
+    pub negotiated_packet_size: Option<usize>,
+    /// Most writes that were waiting for the transfer lock at the same time
+    pub transfer_queue_peak: usize,
+    /// Classes of input readers have seen the device report
+    pub observed_capabilities: ObservedCapabilities,
+}
+
+/// Statistics shared between the device and its readers
+#[derive(Clone, Debug, Default)]
+pub struct SharedStats(Arc<Mutex<DeviceStats>>);
+
+impl SharedStats {
+    /// Updates statistics
+    pub fn update(&self, update: impl FnOnce(&mut DeviceStats)) {
+        update(&mut self.0.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    /// Returns copy of the current statistics
+    pub fn snapshot(&self) -> DeviceStats {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
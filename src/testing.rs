@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        Mutex,
+    },
+    time::{self, Instant},
+};
+
+use crate::{
+    error::MirajazzError,
+    protocol::encode_notification,
+    state::{
+        DeviceState, DeviceStateReader, DeviceStateUpdate, InputParser, InputTransport,
+        OutputTransport,
+    },
+    stats::SharedStats,
+    types::{
+        DeviceInput, DeviceNotification, EncoderMode, InputMode, ProtocolVersion, ReadPolicy,
+        VirtualKey,
+    },
+};
+
+/// Single scripted input, relative to the start of the script
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum ScriptedInput {
+    KeyDown(u8),
+    KeyUp(u8),
+    EncoderDown(u8),
+    EncoderUp(u8),
+    EncoderTwist(u8, i8),
+    Notification(DeviceNotification),
+}
+
+/// Timed sequence of inputs, for testing input handling without hardware
+#[derive(Clone, Debug, Default)]
+pub struct InputScript {
+    steps: Vec<(Duration, ScriptedInput)>,
+}
+
+impl InputScript {
+    /// Builds new empty script
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds input happening at specified time since the start of the script
+    pub fn at(mut self, millis: u64, input: ScriptedInput) -> Self {
+        self.steps.push((Duration::from_millis(millis), input));
+        self.steps.sort_by_key(|(at, _)| *at);
+        self
+    }
+
+    /// Returns steps of the script, ordered by time
+    pub fn steps(&self) -> &[(Duration, ScriptedInput)] {
+        &self.steps
+    }
+
+    /// Short and long (500ms) press of the first key
+    pub fn short_and_long_press() -> Self {
+        Self::new()
+            .at(0, ScriptedInput::KeyDown(0))
+            .at(100, ScriptedInput::KeyUp(0))
+            .at(300, ScriptedInput::KeyDown(0))
+            .at(800, ScriptedInput::KeyUp(0))
+    }
+
+    /// Press of the first encoder, followed by twists in both directions
+    pub fn encoder_press_and_twist() -> Self {
+        Self::new()
+            .at(0, ScriptedInput::EncoderDown(0))
+            .at(50, ScriptedInput::EncoderUp(0))
+            .at(200, ScriptedInput::EncoderTwist(0, 2))
+            .at(300, ScriptedInput::EncoderTwist(0, -1))
+    }
+}
+
+/// Device without hardware, that replays input scripts through a real
+/// [crate::state::DeviceStateReader]
+///
+/// Every scripted input is sent as an input report and read back by the reader, so updates go
+/// through the same parsing, state diffing, encoder modes, virtual keys and read policy as the
+/// hardware ones. Writes, like status requests of [InputMode::Polled], are discarded
+///
+/// Timing relies on tokio time, so scripts run deterministically and instantly
+/// with paused time (`#[tokio::test(start_paused = true)]`)
+pub struct ScriptedDevice {
+    /// State of the scripted buttons, the reader keeps its own
+    buttons: Vec<bool>,
+    /// State of the scripted encoders, the reader keeps its own
+    encoders: Vec<bool>,
+    reports: UnboundedSender<Vec<u8>>,
+    reader: DeviceStateReader,
+}
+
+impl ScriptedDevice {
+    /// Builds scripted device with provided amount of keys and encoders
+    pub fn new(key_count: usize, encoder_count: usize) -> Self {
+        let (reports, receiver) = unbounded_channel();
+        let protocol_version = ProtocolVersion::V3;
+
+        let reader = DeviceStateReader {
+            protocol_version,
+            input_report_size: protocol_version.input_report_size(),
+            supports_both_keypress_states: true,
+            supports_both_encoder_states: true,
+            reader: Arc::new(Mutex::new(InputTransport::Scripted(receiver))),
+            writer: Arc::new(Mutex::new(OutputTransport::Discard)),
+            transfer_lock: Arc::default(),
+            packet_size: Arc::new(AtomicUsize::new(protocol_version.packet_size())),
+            input_mode: InputMode::Push,
+            read_policy: ReadPolicy::Strict,
+            states: std::sync::Mutex::new(DeviceState {
+                buttons: vec![false; key_count],
+                encoders: vec![false; encoder_count],
+            }),
+            process_input: InputParser::Report(parse_scripted_report),
+            stats: SharedStats::default(),
+            queued_reports: Arc::default(),
+            encoder_mode: EncoderMode::Normal,
+            pulses: std::sync::Mutex::default(),
+            logical_keys: Arc::default(),
+            key_codes: HashMap::new(),
+            capability_events: false,
+            acks: Arc::default(),
+            virtual_keys: vec![],
+            virtual_state: std::sync::Mutex::default(),
+            pending_read: Arc::default(),
+        };
+
+        Self {
+            buttons: vec![false; key_count],
+            encoders: vec![false; encoder_count],
+            reports,
+            reader,
+        }
+    }
+
+    pub fn with_supports_both_keypress_states(mut self, supports: bool) -> Self {
+        self.reader.supports_both_keypress_states = supports;
+        self
+    }
+
+    pub fn with_supports_both_encoder_states(mut self, supports: bool) -> Self {
+        self.reader.supports_both_encoder_states = supports;
+        self
+    }
+
+    /// Same as [crate::device::Device::with_encoder_mode]
+    pub fn with_encoder_mode(mut self, encoder_mode: EncoderMode) -> Self {
+        self.reader.encoder_mode = encoder_mode;
+        self
+    }
+
+    /// Same as [crate::device::Device::with_virtual_keys], without validation
+    pub fn with_virtual_keys(mut self, virtual_keys: Vec<VirtualKey>) -> Self {
+        self.reader.virtual_keys = virtual_keys;
+        self
+    }
+
+    /// Same as [crate::device::Device::with_read_policy], strict by default so scripts
+    /// with mistakes fail loudly
+    pub fn with_read_policy(mut self, read_policy: ReadPolicy) -> Self {
+        self.reader.read_policy = read_policy;
+        self
+    }
+
+    /// Same as [crate::device::Device::with_capability_events]
+    pub fn with_capability_events(mut self, capability_events: bool) -> Self {
+        self.reader.capability_events = capability_events;
+        self
+    }
+
+    /// Returns the reader scripted inputs are read by
+    pub fn reader(&self) -> &DeviceStateReader {
+        &self.reader
+    }
+
+    /// Plays the script, calling handler with updates read at the time inputs happen
+    pub async fn play<F>(
+        &mut self,
+        script: &InputScript,
+        mut handler: F,
+    ) -> Result<(), MirajazzError>
+    where
+        F: FnMut(Vec<DeviceStateUpdate>),
+    {
+        let started = Instant::now();
+
+        for (at, input) in script.steps() {
+            time::sleep_until(started + *at).await;
+
+            let report = self.apply(*input);
+
+            // Reader is owned by the device, so it can't be gone
+            let _ = self.reports.send(report);
+
+            handler(self.reader.read(None).await?);
+        }
+
+        Ok(())
+    }
+
+    /// Converts scripted input to the report device would send, updating scripted state
+    fn apply(&mut self, input: ScriptedInput) -> Vec<u8> {
+        let set = |states: &mut Vec<bool>, index: u8, pressed: bool| {
+            if let Some(state) = states.get_mut(index as usize) {
+                *state = pressed;
+            }
+
+            states.iter().map(|state| *state as u8).collect::<Vec<_>>()
+        };
+
+        let (kind, values) = match input {
+            ScriptedInput::KeyDown(key) => (REPORT_BUTTONS, set(&mut self.buttons, key, true)),
+            ScriptedInput::KeyUp(key) => (REPORT_BUTTONS, set(&mut self.buttons, key, false)),
+            ScriptedInput::EncoderDown(encoder) => {
+                (REPORT_ENCODERS, set(&mut self.encoders, encoder, true))
+            }
+            ScriptedInput::EncoderUp(encoder) => {
+                (REPORT_ENCODERS, set(&mut self.encoders, encoder, false))
+            }
+            ScriptedInput::EncoderTwist(encoder, delta) => {
+                let mut twist = vec![0; self.encoders.len()];
+
+                if let Some(change) = twist.get_mut(encoder as usize) {
+                    *change = delta as u8;
+                }
+
+                (REPORT_TWIST, twist)
+            }
+            ScriptedInput::Notification(notification) => {
+                return encode_notification(notification);
+            }
+        };
+
+        let mut report = vec![65, 67, 75, 0, 0, 0, 0, 0, 0, kind, values.len() as u8];
+        report.extend(values);
+
+        report
+    }
+}
+
+/// Kinds of scripted reports, they carry ACK prefix like input reports, followed by the kind,
+/// number of values and state of every button or encoder, or twist of every encoder
+const REPORT_BUTTONS: u8 = 1;
+const REPORT_ENCODERS: u8 = 2;
+const REPORT_TWIST: u8 = 3;
+
+/// Parses reports sent by [ScriptedDevice]
+fn parse_scripted_report(data: &[u8]) -> Result<DeviceInput, MirajazzError> {
+    let count = *data.get(10).ok_or(MirajazzError::BadData)? as usize;
+    let values = data.get(11..11 + count).ok_or(MirajazzError::BadData)?;
+
+    let states = || values.iter().map(|value| *value != 0).collect();
+
+    match data[9] {
+        REPORT_BUTTONS => Ok(DeviceInput::ButtonStateChange(states())),
+        REPORT_ENCODERS => Ok(DeviceInput::EncoderStateChange(states())),
+        REPORT_TWIST => Ok(DeviceInput::EncoderTwist(
+            values.iter().map(|value| *value as i8).collect(),
+        )),
+        _ => Err(MirajazzError::BadData),
+    }
+}
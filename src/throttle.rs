@@ -0,0 +1,255 @@
+use futures_lite::FutureExt;
+use std::{
+    future::Future,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    sync::Notify,
+    time::{self, Instant},
+};
+
+use crate::error::MirajazzError;
+
+/// Coalesces rapid updates of a single setting, sending at most one per interval
+///
+/// Every update waits until it's sent or superseded by a newer one. Superseded updates return
+/// right away without sending, so the setting always settles on the latest value. If the update
+/// that is sending gets cancelled, the newest waiting update takes over
+#[derive(Debug)]
+pub struct Throttle {
+    interval: Duration,
+    state: Mutex<ThrottleState>,
+    notify: Notify,
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    /// Ticket of the latest update
+    latest: u64,
+    /// Some update is waiting for the interval or sending
+    sending: bool,
+    /// When the last update started sending
+    last_sent: Option<Instant>,
+}
+
+/// Releases the sending role, even if the update was cancelled, and wakes waiting updates
+struct SendingGuard<'a>(&'a Throttle);
+
+impl Drop for SendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.lock().sending = false;
+        self.0.notify.notify_waiters();
+    }
+}
+
+impl Throttle {
+    /// Builds throttle sending at most one update per interval
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            state: Mutex::new(ThrottleState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Returns minimal interval between sent updates
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Sends the update once the interval since the previous one passes, unless a newer update
+    /// supersedes it first, in which case it returns `Ok(())` without sending
+    ///
+    /// Errors of sending are returned to the update that was sent
+    pub async fn send<F>(&self, update: F) -> Result<(), MirajazzError>
+    where
+        F: Future<Output = Result<(), MirajazzError>>,
+    {
+        let ticket = {
+            let mut state = self.lock();
+            state.latest += 1;
+            state.latest
+        };
+
+        self.notify.notify_waiters();
+
+        loop {
+            let notified = self.notify.notified();
+
+            {
+                let mut state = self.lock();
+
+                if state.latest != ticket {
+                    return Ok(());
+                }
+
+                if !state.sending {
+                    state.sending = true;
+                    break;
+                }
+            }
+
+            notified.await;
+        }
+
+        let _sending = SendingGuard(self);
+
+        loop {
+            let notified = self.notify.notified();
+
+            let sleep = {
+                let mut state = self.lock();
+
+                if state.latest != ticket {
+                    return Ok(());
+                }
+
+                match state.last_sent {
+                    Some(last_sent) if last_sent + self.interval > Instant::now() => {
+                        last_sent + self.interval
+                    }
+                    _ => {
+                        state.last_sent = Some(Instant::now());
+                        break;
+                    }
+                }
+            };
+
+            // Wakes up early when superseded, so the newer update takes over
+            time::sleep_until(sleep).or(notified).await;
+        }
+
+        update.await
+    }
+
+    /// Discards waiting updates, they return `Ok(())` without sending
+    pub fn cancel(&self) {
+        self.lock().latest += 1;
+        self.notify.notify_waiters();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ThrottleState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    type Sent = Arc<Mutex<Vec<(Duration, u8)>>>;
+
+    fn record(
+        sent: &Sent,
+        started: Instant,
+        value: u8,
+    ) -> impl Future<Output = Result<(), MirajazzError>> {
+        let sent = sent.clone();
+
+        async move {
+            sent.lock().unwrap().push((started.elapsed(), value));
+
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rapid_updates_settle_on_the_last_value() {
+        let throttle = Arc::new(Throttle::new(Duration::from_millis(50)));
+        let sent: Sent = Arc::default();
+        let started = Instant::now();
+        let mut tasks = vec![];
+
+        for value in 0..100u8 {
+            let throttle = throttle.clone();
+            let update = record(&sent, started, value);
+
+            tasks.push(tokio::spawn(async move { throttle.send(update).await }));
+
+            time::sleep(Duration::from_millis(1)).await;
+        }
+
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        let sent = sent.lock().unwrap();
+
+        assert!(sent.len() <= 4, "sent {} updates", sent.len());
+        assert_eq!(sent.last().map(|(_, value)| *value), Some(99));
+
+        for pair in sent.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= Duration::from_millis(50));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_update_returns_error_to_its_caller() {
+        let throttle = Throttle::new(Duration::from_millis(50));
+
+        let result = throttle.send(async { Err(MirajazzError::BadData) }).await;
+
+        assert!(matches!(result, Err(MirajazzError::BadData)));
+        assert!(throttle.send(async { Ok(()) }).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_sender_hands_over_to_newer_update() {
+        let throttle = Arc::new(Throttle::new(Duration::from_millis(50)));
+        let sent: Sent = Arc::default();
+        let started = Instant::now();
+
+        // Gets stuck while sending, until it's cancelled
+        let stuck = {
+            let throttle = throttle.clone();
+
+            tokio::spawn(async move { throttle.send(std::future::pending()).await })
+        };
+
+        time::sleep(Duration::from_millis(10)).await;
+
+        let waiting = {
+            let throttle = throttle.clone();
+            let update = record(&sent, started, 1);
+
+            tokio::spawn(async move { throttle.send(update).await })
+        };
+
+        time::sleep(Duration::from_millis(10)).await;
+        assert!(sent.lock().unwrap().is_empty());
+
+        stuck.abort();
+
+        waiting.await.unwrap().unwrap();
+
+        let sent = sent.lock().unwrap();
+
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].1, 1);
+        assert!(sent[0].0 >= Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancel_discards_waiting_updates() {
+        let throttle = Arc::new(Throttle::new(Duration::from_millis(50)));
+        let sent: Sent = Arc::default();
+        let started = Instant::now();
+
+        throttle.send(record(&sent, started, 0)).await.unwrap();
+
+        let waiting = {
+            let throttle = throttle.clone();
+            let update = record(&sent, started, 1);
+
+            tokio::spawn(async move { throttle.send(update).await })
+        };
+
+        time::sleep(Duration::from_millis(10)).await;
+        throttle.cancel();
+
+        assert!(waiting.await.unwrap().is_ok());
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+}
@@ -1,17 +1,381 @@
-use async_hid::{Device as AsyncHidDevice, DeviceInfo as AsyncHidDeviceInfo};
+use async_hid::{
+    Device as AsyncHidDevice, DeviceId as AsyncHidDeviceId, DeviceInfo as AsyncHidDeviceInfo,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{Display, Formatter},
+    hash::{Hash, Hasher},
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    device::normalize_serial,
+    error::MirajazzError,
+    images::ConversionMeta,
+    kind::Kind,
+    protocol::{crc16, sum16},
+};
 
 pub type HidDeviceInfo = AsyncHidDeviceInfo;
 pub type HidDevice = AsyncHidDevice;
+pub type HidDeviceId = AsyncHidDeviceId;
+
+/// Identity of the device, used as a key by the whole library
+///
+/// Devices are equal if vid, pid and normalized serial number match. Platform path is only
+/// compared for devices without serial number
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceId {
+    pub vid: u16,
+    pub pid: u16,
+    /// Normalized serial number, see [crate::device::normalize_serial]
+    pub serial: Option<String>,
+    /// Platform path of the device
+    pub path: Option<String>,
+}
+
+impl DeviceId {
+    /// Builds device id, normalizing the serial number
+    ///
+    /// Serial that is empty after normalization is treated as missing, so such devices are told apart by path
+    pub fn new(vid: u16, pid: u16, serial: Option<&str>, path: Option<String>) -> Self {
+        Self {
+            vid,
+            pid,
+            serial: serial
+                .map(normalize_serial)
+                .filter(|serial| !serial.is_empty()),
+            path,
+        }
+    }
+
+    /// Builds id of the device speaking provided protocol version
+    ///
+    /// Protocol version 1 devices share a hardcoded serial number, so they are told apart by path
+    pub fn from_info(info: &HidDeviceInfo, protocol_version: ProtocolVersion) -> Self {
+        let serial = match protocol_version {
+            ProtocolVersion::V1 => None,
+            _ => info.serial_number.as_deref(),
+        };
+
+        Self::new(
+            info.vendor_id,
+            info.product_id,
+            serial,
+            Some(platform_path(&info.id)),
+        )
+    }
+
+    /// Returns identity in the form of `(vid, pid, serial)` tuple, used by older versions
+    pub fn as_tuple(&self) -> (u16, u16, String) {
+        (self.vid, self.pid, self.serial.clone().unwrap_or_default())
+    }
+}
+
+/// Returns printable platform path of the device
+pub fn platform_path(id: &HidDeviceId) -> String {
+    match id {
+        #[cfg(target_os = "linux")]
+        HidDeviceId::DevPath(path) => path.display().to_string(),
+        #[cfg(target_os = "windows")]
+        HidDeviceId::UncPath(path) => path.to_string(),
+        #[cfg(target_os = "macos")]
+        HidDeviceId::RegistryEntryId(id) => id.to_string(),
+        _ => format!("{:?}", id),
+    }
+}
+
+/// Protocol version is taken from the known model, see [DeviceId::from_info]
+impl From<&HidDeviceInfo> for DeviceId {
+    fn from(info: &HidDeviceInfo) -> Self {
+        match Kind::from_vid_pid(info.vendor_id, info.product_id) {
+            Some(kind) => Self::from_info(info, kind.protocol_version),
+            None => Self::new(
+                info.vendor_id,
+                info.product_id,
+                info.serial_number.as_deref(),
+                Some(platform_path(&info.id)),
+            ),
+        }
+    }
+}
+
+impl From<(u16, u16, String)> for DeviceId {
+    fn from((vid, pid, serial): (u16, u16, String)) -> Self {
+        Self::new(vid, pid, Some(&serial), None)
+    }
+}
+
+impl PartialEq for DeviceId {
+    fn eq(&self, other: &Self) -> bool {
+        self.vid == other.vid
+            && self.pid == other.pid
+            && self.serial == other.serial
+            && (self.serial.is_some() || self.path == other.path)
+    }
+}
+
+impl Eq for DeviceId {}
+
+impl Hash for DeviceId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.vid.hash(state);
+        self.pid.hash(state);
+        self.serial.hash(state);
+
+        if self.serial.is_none() {
+            self.path.hash(state);
+        }
+    }
+}
+
+impl Display for DeviceId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04X}:{:04X}", self.vid, self.pid)?;
+
+        match (&self.serial, &self.path) {
+            (Some(serial), _) => write!(f, " SN {}", serial),
+            (None, Some(path)) => write!(f, " at {}", path),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Protocol version of the device, see README for the differences between versions
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    /// Devices with very old firmware, set automatically when needed, use [ProtocolVersion::V1]
+    V0,
+    /// 512-bytes packets
+    V1,
+    /// 1024-bytes packets, requires STP to commit clearing the screen
+    V2,
+    /// Same as [ProtocolVersion::V2], but supports both keypress states
+    V3,
+}
+
+impl ProtocolVersion {
+    /// Size of output packets, without report id
+    pub fn packet_size(&self) -> usize {
+        match self {
+            ProtocolVersion::V0 | ProtocolVersion::V1 => 512,
+            ProtocolVersion::V2 | ProtocolVersion::V3 => 1024,
+        }
+    }
+
+    /// Size of input reports
+    pub fn input_report_size(&self) -> usize {
+        match self {
+            ProtocolVersion::V0 | ProtocolVersion::V1 => 512,
+            ProtocolVersion::V2 | ProtocolVersion::V3 => 1024,
+        }
+    }
+
+    /// Whether STP is required to commit clearing the screen
+    pub fn requires_stp_after_clear(&self) -> bool {
+        match self {
+            ProtocolVersion::V0 | ProtocolVersion::V1 => false,
+            ProtocolVersion::V2 | ProtocolVersion::V3 => true,
+        }
+    }
+
+    /// Whether devices report both "Up" and "Down" states
+    pub fn supports_both_states(&self) -> bool {
+        match self {
+            ProtocolVersion::V0 | ProtocolVersion::V1 | ProtocolVersion::V2 => false,
+            ProtocolVersion::V3 => true,
+        }
+    }
+
+    /// Whether input reports are prefixed with ACK
+    pub fn has_ack_prefix(&self) -> bool {
+        match self {
+            ProtocolVersion::V0 => false,
+            ProtocolVersion::V1 | ProtocolVersion::V2 | ProtocolVersion::V3 => true,
+        }
+    }
+}
+
+/// Numeric protocol versions are still accepted for compatibility
+impl TryFrom<usize> for ProtocolVersion {
+    type Error = MirajazzError;
+
+    fn try_from(version: usize) -> Result<Self, Self::Error> {
+        match version {
+            0 => Ok(ProtocolVersion::V0),
+            1 => Ok(ProtocolVersion::V1),
+            2 => Ok(ProtocolVersion::V2),
+            3 => Ok(ProtocolVersion::V3),
+            _ => Err(MirajazzError::UnsupportedProtocolVersion(version)),
+        }
+    }
+}
+
+/// How the device delivers input reports
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum InputMode {
+    /// Device sends input reports by itself
+    #[default]
+    Push,
+    /// Device only answers to the status request with the current state of the buttons
+    Polled {
+        /// Status request, padded to the packet size before sending
+        request: Vec<u8>,
+        /// Delay between the requests
+        interval: Duration,
+    },
+}
+
+/// Encoder that is reported as a pair of buttons, one per direction
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct PulseDirection {
+    /// Index of the encoder button that pulses
+    pub index: u8,
+    /// Encoder that is twisted
+    pub encoder: u8,
+    /// Twist reported for a single pulse, usually 1 or -1
+    pub direction: i8,
+}
+
+/// What makes a virtual key go down and up
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum VirtualTrigger {
+    /// Key held for at least the duration, reported right after the key is released
+    LongPress { key: u8, after: Duration },
+    /// Encoder pressed down, replaces the encoder press
+    EncoderPress(u8),
+    /// Touch point pressed at X coordinate in `start..end`, replaces the touch
+    TouchRegion { key: u8, start: u16, end: u16 },
+}
+
+/// Key reported by readers beyond the physical ones, see [crate::device::Device::with_virtual_keys]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct VirtualKey {
+    /// Index of the virtual key, must be at least the key count of the device
+    pub index: u8,
+    /// Input that triggers the key
+    pub trigger: VirtualTrigger,
+}
+
+/// Order of bits inside each byte of a button bitmap
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum BitOrder {
+    /// Lowest bit of the byte comes first
+    #[default]
+    LsbFirst,
+    /// Highest bit of the byte comes first
+    MsbFirst,
+}
+
+/// Layout of input reports carrying state of every button as a bitmap, one bit per key
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct ButtonBitmap {
+    /// Offset of the first key's bit from the start of the report, in bits
+    pub offset: usize,
+    /// Order of bits inside each byte
+    pub bit_order: BitOrder,
+    /// Number of keys in the bitmap
+    pub key_count: usize,
+}
+
+impl ButtonBitmap {
+    /// Parses state of every button from the input report
+    ///
+    /// Returns [MirajazzError::BadData] if report is too short to carry the whole bitmap
+    pub fn parse(&self, data: &[u8]) -> Result<DeviceInput, MirajazzError> {
+        if (self.offset + self.key_count).div_ceil(8) > data.len() {
+            return Err(MirajazzError::BadData);
+        }
+
+        let buttons = (self.offset..self.offset + self.key_count)
+            .map(|bit| {
+                let shift = match self.bit_order {
+                    BitOrder::LsbFirst => bit % 8,
+                    BitOrder::MsbFirst => 7 - bit % 8,
+                };
+
+                data[bit / 8] >> shift & 1 == 1
+            })
+            .collect();
+
+        Ok(DeviceInput::ButtonStateChange(buttons))
+    }
+}
+
+/// How readers interpret encoder inputs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum EncoderMode {
+    /// Encoder inputs are reported as is
+    #[default]
+    Normal,
+    /// Short press/release pulses of mapped encoder buttons are reported as twists,
+    /// for devices that report each detent as a pulse
+    ///
+    /// Presses held longer than `max_pulse` are reported as [crate::state::DeviceStateUpdate::EncoderDown]
+    /// and [crate::state::DeviceStateUpdate::EncoderUp] on release
+    PulseAsTwist {
+        direction_map: Vec<PulseDirection>,
+        max_pulse: Duration,
+    },
+}
+
+/// What to do with malformed input reports, and inputs the device wasn't declared to have
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum ReadPolicy {
+    /// Malformed reports and unexpected or out of range inputs are logged, counted and skipped
+    #[default]
+    Lenient,
+    /// Malformed reports are returned as [crate::error::MirajazzError::BadData], unexpected
+    /// and out of range inputs as [crate::error::MirajazzError::UnexpectedInput],
+    /// [crate::error::MirajazzError::InvalidKeyIndex] or [crate::error::MirajazzError::InvalidEncoderIndex]
+    Strict,
+}
+
+/// What flushes do while the display is turned off
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum DisplayOffFlush {
+    /// Images stay pending until [crate::device::Device::display_on]
+    #[default]
+    Queue,
+    /// Display is turned back on before flushing
+    Reenable,
+}
 
 /// Connection / Disconnection event for watchers
+///
+/// New kinds of events are added over time, so matches need a catch-all arm
 #[derive(Eq, PartialEq, Hash, Debug)]
+#[non_exhaustive]
 pub enum DeviceLifecycleEvent {
     Connected(HidDeviceInfo),
     Disconnected(HidDeviceInfo),
+    /// Device came back on the same platform path, but with a missing or different serial,
+    /// only reported by [crate::device::DeviceWatcher::with_fuzzy_reconnect]
+    Reconnected {
+        previous: HidDeviceInfo,
+        current: HidDeviceInfo,
+    },
+}
+
+impl DeviceLifecycleEvent {
+    /// Returns id of the device the event is about, current one for reconnections
+    pub fn device_id(&self) -> DeviceId {
+        match self {
+            DeviceLifecycleEvent::Connected(info)
+            | DeviceLifecycleEvent::Disconnected(info)
+            | DeviceLifecycleEvent::Reconnected { current: info, .. } => DeviceId::from(info),
+        }
+    }
 }
 
 /// Type of input that the device produced
+///
+/// New kinds of input are added over time, so matches need a catch-all arm
 #[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum DeviceInput {
     /// No data was passed from the device
     NoData,
@@ -22,8 +386,62 @@ pub enum DeviceInput {
     /// Encoder/Knob was pressed
     EncoderStateChange(Vec<bool>),
 
-    /// Encoder/Knob was twisted/turned
+    /// Encoder/Knob was twisted/turned, one entry per encoder, zero for encoders that didn't move
     EncoderTwist(Vec<i8>),
+
+    /// Touch point was pressed or released, with X coordinate of the touch if device reported it
+    TouchKey {
+        index: u8,
+        x: Option<u16>,
+        pressed: bool,
+    },
+
+    /// Device reported a change of its own status
+    Notification(DeviceNotification),
+}
+
+/// Class of input a device can report
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Capability {
+    Buttons,
+    Encoders,
+    Touch,
+}
+
+/// Classes of input the device was actually seen reporting, as opposed to declared counts
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct ObservedCapabilities {
+    pub buttons: bool,
+    pub encoders: bool,
+    pub touch: bool,
+}
+
+impl ObservedCapabilities {
+    /// Marks capability as observed, returns true if it wasn't observed before
+    pub fn observe(&mut self, capability: Capability) -> bool {
+        let seen = match capability {
+            Capability::Buttons => &mut self.buttons,
+            Capability::Encoders => &mut self.encoders,
+            Capability::Touch => &mut self.touch,
+        };
+
+        !std::mem::replace(seen, true)
+    }
+}
+
+/// Status notification sent by the device by itself
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum DeviceNotification {
+    /// Device went to sleep
+    SleepEntered,
+    /// Device woke up
+    WokeUp,
+    /// Device is overheating
+    Overheat,
+    /// Device failed to render an image, see [crate::device::Device::resend_key]
+    RenderError { code: u8, key: Option<u8> },
+    /// Notification with unknown opcode
+    Raw([u8; 3]),
 }
 
 impl DeviceInput {
@@ -33,17 +451,132 @@ impl DeviceInput {
     }
 }
 
+/// Checksum of image data sent in the transfer header, for firmwares that validate it
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum ChecksumKind {
+    /// Wrapping 16-bit sum of the bytes
+    Sum16,
+    /// CRC-16/CCITT-FALSE
+    Crc16,
+}
+
+impl ChecksumKind {
+    /// Computes checksum of the data
+    pub fn compute(&self, data: &[u8]) -> u16 {
+        match self {
+            ChecksumKind::Sum16 => sum16(data),
+            ChecksumKind::Crc16 => crc16(data),
+        }
+    }
+}
+
+/// How buttons are cleared
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum ClearStrategy {
+    /// Clear command of the device
+    #[default]
+    Command,
+    /// Black image of provided format, for panels that keep a ghost of the previous image after the command
+    BlackImage(ImageFormat),
+    /// Clear command, followed by black image of provided format
+    CommandThenBlack(ImageFormat),
+}
+
+impl ClearStrategy {
+    /// Checks if the clear command is sent
+    pub fn sends_command(&self) -> bool {
+        matches!(
+            self,
+            ClearStrategy::Command | ClearStrategy::CommandThenBlack(_)
+        )
+    }
+
+    /// Returns format of black images, [None] if they aren't sent
+    pub fn black_image_format(&self) -> Option<ImageFormat> {
+        match self {
+            ClearStrategy::Command => None,
+            ClearStrategy::BlackImage(format) | ClearStrategy::CommandThenBlack(format) => {
+                Some(*format)
+            }
+        }
+    }
+}
+
 /// Image format used by the device
-#[derive(Copy, Clone, Debug, Hash)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub struct ImageFormat {
     /// Image format/mode
     pub mode: ImageMode,
     /// Image size
     pub size: (usize, usize),
-    /// Image rotation
-    pub rotation: ImageRotation,
-    /// Image mirroring
-    pub mirror: ImageMirroring,
+    /// Rotation and mirroring applied before encoding
+    pub transform: ImageTransform,
+    /// Encoder settings, ignored if they don't match the mode
+    pub encoding: EncodingOptions,
+}
+
+impl ImageFormat {
+    /// Builds JPEG format of provided size, without transform and with default encoder settings
+    pub const fn jpeg(size: (usize, usize)) -> Self {
+        Self {
+            mode: ImageMode::JPEG,
+            size,
+            transform: ImageTransform::NONE,
+            encoding: EncodingOptions::Jpeg(JpegOptions::DEFAULT),
+        }
+    }
+
+    /// Builds BMP format of provided size, without transform
+    pub const fn bmp(size: (usize, usize)) -> Self {
+        Self {
+            mode: ImageMode::BMP,
+            size,
+            transform: ImageTransform::NONE,
+            encoding: EncodingOptions::Bmp(BmpOptions::DEFAULT),
+        }
+    }
+
+    /// Returns the same format with provided transform
+    pub const fn with_transform(mut self, transform: ImageTransform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    /// Returns the same format with provided encoder settings
+    pub const fn with_encoding(mut self, encoding: EncodingOptions) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Returns JPEG encoder settings, defaults if encoding doesn't specify them
+    pub fn jpeg_options(&self) -> JpegOptions {
+        match self.encoding {
+            EncodingOptions::Jpeg(options) => options,
+            _ => JpegOptions::DEFAULT,
+        }
+    }
+
+    /// Returns size of the image sent to the device, after rotation is applied
+    pub fn encoded_size(&self) -> (usize, usize) {
+        let (width, height) = self.size;
+
+        match self.transform.rotation {
+            ImageRotation::Rot90 | ImageRotation::Rot270 => (height, width),
+            ImageRotation::Rot0 | ImageRotation::Rot180 => (width, height),
+        }
+    }
+
+    /// Returns the same format, resized so the image sent to the device has provided size
+    pub fn with_encoded_size(self, size: (usize, usize)) -> Self {
+        let (width, height) = size;
+
+        let size = match self.transform.rotation {
+            ImageRotation::Rot90 | ImageRotation::Rot270 => (height, width),
+            ImageRotation::Rot0 | ImageRotation::Rot180 => (width, height),
+        };
+
+        Self { size, ..self }
+    }
 }
 
 impl Default for ImageFormat {
@@ -51,14 +584,111 @@ impl Default for ImageFormat {
         Self {
             mode: ImageMode::None,
             size: (0, 0),
-            rotation: ImageRotation::Rot0,
-            mirror: ImageMirroring::None,
+            transform: ImageTransform::NONE,
+            encoding: EncodingOptions::Default,
+        }
+    }
+}
+
+/// Image format with transform settings inline, as it was before [ImageTransform] was split out
+#[deprecated(note = "Use ImageFormat with ImageTransform instead")]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct LegacyImageFormat {
+    /// Image format/mode
+    pub mode: ImageMode,
+    /// Image size
+    pub size: (usize, usize),
+    /// Image rotation
+    pub rotation: ImageRotation,
+    /// Image mirroring
+    pub mirror: ImageMirroring,
+}
+
+#[allow(deprecated)]
+impl From<LegacyImageFormat> for ImageFormat {
+    fn from(format: LegacyImageFormat) -> Self {
+        Self {
+            mode: format.mode,
+            size: format.size,
+            transform: ImageTransform::new(format.rotation, format.mirror),
+            encoding: EncodingOptions::Default,
         }
     }
 }
 
+/// Rotation and mirroring applied to images before encoding
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ImageTransform {
+    /// Image rotation
+    pub rotation: ImageRotation,
+    /// Image mirroring
+    pub mirror: ImageMirroring,
+}
+
+impl ImageTransform {
+    /// No rotation and no mirroring
+    pub const NONE: Self = Self::new(ImageRotation::Rot0, ImageMirroring::None);
+
+    /// Builds transform from rotation and mirroring
+    pub const fn new(rotation: ImageRotation, mirror: ImageMirroring) -> Self {
+        Self { rotation, mirror }
+    }
+}
+
+impl Default for ImageTransform {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Encoder settings, per image mode
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum EncodingOptions {
+    /// Default settings of the mode
+    #[default]
+    Default,
+    /// Bitmap encoder settings
+    Bmp(BmpOptions),
+    /// Jpeg encoder settings
+    Jpeg(JpegOptions),
+}
+
+/// Jpeg encoder settings
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct JpegOptions {
+    /// Quality, value range is 1 - 100
+    pub quality: u8,
+}
+
+impl JpegOptions {
+    /// Settings used when none are specified
+    pub const DEFAULT: Self = Self::new(90);
+
+    /// Builds settings with provided quality
+    pub const fn new(quality: u8) -> Self {
+        Self { quality }
+    }
+}
+
+impl Default for JpegOptions {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Bitmap encoder settings, there are none for now
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub struct BmpOptions {}
+
+impl BmpOptions {
+    /// Settings used when none are specified
+    pub const DEFAULT: Self = Self {};
+}
+
 /// Image rotation
-#[derive(Copy, Clone, Debug, Hash)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ImageRotation {
     /// No rotation
     Rot0,
@@ -71,7 +701,8 @@ pub enum ImageRotation {
 }
 
 /// Image mirroring
-#[derive(Copy, Clone, Debug, Hash)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ImageMirroring {
     /// No image mirroring
     None,
@@ -84,7 +715,8 @@ pub enum ImageMirroring {
 }
 
 /// Image format
-#[derive(Copy, Clone, Debug, Hash)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ImageMode {
     /// No image
     None,
@@ -93,3 +725,344 @@ pub enum ImageMode {
     /// Jpeg image
     JPEG,
 }
+
+/// Group of keys sharing the same image format, for devices with multiple kinds of screens
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Zone {
+    /// Key indices belonging to the zone
+    pub key_range: Range<u8>,
+    /// Image format used by the keys of the zone
+    pub image_format: ImageFormat,
+}
+
+impl Zone {
+    /// Checks if the key belongs to the zone
+    pub fn contains(&self, key: u8) -> bool {
+        self.key_range.contains(&key)
+    }
+}
+
+/// Position on the key grid, zero-based
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GridPosition {
+    pub row: usize,
+    pub column: usize,
+}
+
+/// Touch strip or secondary screen of the device
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct StripGeometry {
+    /// Resolution of the strip
+    pub size: (usize, usize),
+    /// Row of the grid the strip is drawn at, spanning all columns
+    pub row: usize,
+}
+
+/// Physical arrangement of the device, for drawing previews
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Geometry {
+    /// Resolution of a single key
+    pub key_size: (usize, usize),
+    /// Amount of rows of keys
+    pub rows: usize,
+    /// Amount of columns of keys
+    pub columns: usize,
+    /// Approximate gap between keys, in pixels of key resolution, if known
+    pub gap: Option<(usize, usize)>,
+    /// Touch strip, if device has one
+    pub strip: Option<StripGeometry>,
+    /// Positions of encoders, relative to the key grid
+    pub encoders: Vec<GridPosition>,
+}
+
+impl Geometry {
+    /// Checks that the grid fits provided amount of keys and encoders exactly
+    ///
+    /// Returns [MirajazzError::InvalidGeometry] otherwise
+    pub fn validate(&self, key_count: usize, encoder_count: usize) -> Result<(), MirajazzError> {
+        if self.rows * self.columns != key_count || self.encoders.len() != encoder_count {
+            return Err(MirajazzError::InvalidGeometry);
+        }
+
+        Ok(())
+    }
+
+    /// Returns physical index of the key at provided logical index, for device rotated to provided orientation
+    ///
+    /// Logical keys go row by row as seen by the user, so logical key 0 is always at the top left
+    pub fn physical_key(&self, key: u8, orientation: Orientation) -> Option<u8> {
+        let (rows, columns) = (self.rows, self.columns);
+
+        let logical_columns = match orientation {
+            Orientation::Rot90 | Orientation::Rot270 => rows,
+            Orientation::Normal | Orientation::Rot180 => columns,
+        };
+
+        let key = key as usize;
+
+        if logical_columns == 0 || key >= rows * columns {
+            return None;
+        }
+
+        let (logical_row, logical_column) = (key / logical_columns, key % logical_columns);
+
+        let (row, column) = match orientation {
+            Orientation::Normal => (logical_row, logical_column),
+            Orientation::Rot90 => (rows - 1 - logical_column, logical_row),
+            Orientation::Rot180 => (rows - 1 - logical_row, columns - 1 - logical_column),
+            Orientation::Rot270 => (logical_column, columns - 1 - logical_row),
+        };
+
+        Some((row * columns + column) as u8)
+    }
+
+    /// Returns position of the key on the grid, keys go row by row
+    pub fn key_position(&self, key: u8) -> Option<GridPosition> {
+        let key = key as usize;
+
+        if self.columns == 0 || key >= self.rows * self.columns {
+            return None;
+        }
+
+        Some(GridPosition {
+            row: key / self.columns,
+            column: key % self.columns,
+        })
+    }
+}
+
+/// Clockwise rotation of the whole device, for devices mounted in a different orientation
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum Orientation {
+    /// Device is mounted as designed
+    #[default]
+    Normal,
+    /// 90 degrees clockwise
+    Rot90,
+    /// 180 degrees
+    Rot180,
+    /// 90 degrees counter-clockwise
+    Rot270,
+}
+
+/// Command for uploading images that are stored in device flash
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct UploadCommand {
+    /// Opcode of the upload command
+    pub opcode: [u8; 3],
+    /// Id of the stored image slot
+    pub target: u8,
+}
+
+/// Brightness percentage, guaranteed to be in 0 - 100 range
+#[derive(Copy, Clone, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Brightness(u8);
+
+impl Brightness {
+    /// Returns [MirajazzError::InvalidBrightness] if percent is out of 0 - 100 range
+    pub fn new(percent: u8) -> Result<Self, MirajazzError> {
+        if percent > 100 {
+            return Err(MirajazzError::InvalidBrightness);
+        }
+
+        Ok(Self(percent))
+    }
+
+    /// Clamps percent to 0 - 100 range
+    pub fn clamped(percent: u8) -> Self {
+        Self(percent.min(100))
+    }
+
+    /// Returns brightness percentage
+    pub fn percent(self) -> u8 {
+        self.0
+    }
+}
+
+/// Outcome of flushing a single key
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum KeyFlushOutcome {
+    /// Image was sent to the device
+    Sent,
+    /// Image is already displayed on the device, nothing was sent
+    SkippedIdentical,
+    /// Sending the image failed, it stays pending and is sent again starting from the header
+    /// on the next flush, keys that completed are not sent again
+    Failed {
+        error: String,
+        /// Payload bytes of the image that were sent before the failure
+        bytes_sent: usize,
+    },
+}
+
+/// Everything known about the connected device, see [crate::device::Device::query_info]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MirajazzDeviceInfo {
+    /// Vendor ID of the device
+    pub vendor_id: u16,
+    /// Product ID of the device
+    pub product_id: u16,
+    /// Serial number, if reported
+    pub serial_number: Option<String>,
+    /// Product string of the descriptor, if present
+    pub product: Option<String>,
+    /// Manufacturer string of the descriptor, if present
+    pub manufacturer: Option<String>,
+    /// Firmware version, if device reported it
+    pub firmware_version: Option<String>,
+    /// Model reported by the firmware, if present
+    pub model: Option<String>,
+    /// Number of keys
+    pub key_count: usize,
+    /// Number of encoders
+    pub encoder_count: usize,
+    /// Size of output packets, without report id
+    pub packet_size: usize,
+    /// Protocol version in use
+    pub protocol_version: ProtocolVersion,
+}
+
+/// What [crate::device::Device::apply_page] did, keys are the ones passed by the user
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PageSummary {
+    /// Keys which images were sent
+    pub changed: Vec<u8>,
+    /// Keys which already displayed the same image
+    pub skipped: Vec<u8>,
+    /// Keys that had an image, but weren't on the page
+    pub cleared: Vec<u8>,
+}
+
+/// Per-key outcomes of the last flush
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FlushReport {
+    /// When the flush finished, `None` if nothing was flushed yet
+    pub finished_at: Option<SystemTime>,
+    /// Outcome for every key that was pending, ordered by key
+    pub keys: Vec<(u8, KeyFlushOutcome)>,
+    /// What the conversion did to images of sent keys, for images set from [image::DynamicImage]
+    pub conversions: Vec<(u8, ConversionMeta)>,
+}
+
+impl FlushReport {
+    /// Returns keys that failed to flush
+    pub fn failed_keys(&self) -> Vec<u8> {
+        self.keys
+            .iter()
+            .filter(|(_, outcome)| matches!(outcome, KeyFlushOutcome::Failed { .. }))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{DeviceState, DeviceStateUpdate};
+
+    fn buttons(input: DeviceInput) -> Vec<bool> {
+        match input {
+            DeviceInput::ButtonStateChange(buttons) => buttons,
+            input => panic!("unexpected input {:?}", input),
+        }
+    }
+
+    #[test]
+    fn bitmap_two_keys_in_one_report() {
+        let bitmap = ButtonBitmap {
+            offset: 8,
+            bit_order: BitOrder::LsbFirst,
+            key_count: 6,
+        };
+
+        let parsed = buttons(bitmap.parse(&[0x01, 0b0000_0101]).unwrap());
+
+        assert_eq!(parsed, vec![true, false, true, false, false, false]);
+    }
+
+    #[test]
+    fn bitmap_bit_orders() {
+        let report = [0b1000_0010, 0b0000_0001];
+
+        let lsb = ButtonBitmap {
+            offset: 0,
+            bit_order: BitOrder::LsbFirst,
+            key_count: 10,
+        };
+        let msb = ButtonBitmap {
+            bit_order: BitOrder::MsbFirst,
+            ..lsb
+        };
+
+        let mut expected = vec![false; 10];
+        expected[1] = true;
+        expected[7] = true;
+        expected[8] = true;
+        assert_eq!(buttons(lsb.parse(&report).unwrap()), expected);
+
+        let mut expected = vec![false; 10];
+        expected[0] = true;
+        expected[6] = true;
+        assert_eq!(buttons(msb.parse(&report).unwrap()), expected);
+    }
+
+    #[test]
+    fn bitmap_rolling_releases() {
+        let bitmap = ButtonBitmap {
+            offset: 0,
+            bit_order: BitOrder::LsbFirst,
+            key_count: 4,
+        };
+        let mut state = DeviceState {
+            buttons: vec![false; 4],
+            encoders: vec![],
+        };
+
+        let mut apply = |report: u8| {
+            state
+                .apply(bitmap.parse(&[report]).unwrap(), true, true)
+                .unwrap()
+        };
+
+        assert!(matches!(
+            apply(0b0001)[..],
+            [DeviceStateUpdate::ButtonDown(0)]
+        ));
+        assert!(matches!(
+            apply(0b0011)[..],
+            [DeviceStateUpdate::ButtonDown(1)]
+        ));
+        assert!(matches!(
+            apply(0b0010)[..],
+            [DeviceStateUpdate::ButtonUp(0)]
+        ));
+        assert!(matches!(
+            apply(0b0100)[..],
+            [
+                DeviceStateUpdate::ButtonUp(1),
+                DeviceStateUpdate::ButtonDown(2)
+            ]
+        ));
+        assert!(matches!(
+            apply(0b0000)[..],
+            [DeviceStateUpdate::ButtonUp(2)]
+        ));
+    }
+
+    #[test]
+    fn bitmap_too_short_report() {
+        let bitmap = ButtonBitmap {
+            offset: 4,
+            bit_order: BitOrder::LsbFirst,
+            key_count: 8,
+        };
+
+        assert!(matches!(bitmap.parse(&[0xff]), Err(MirajazzError::BadData)));
+        assert!(bitmap.parse(&[0xff, 0xff]).is_ok());
+    }
+}